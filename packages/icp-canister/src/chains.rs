@@ -0,0 +1,92 @@
+use crate::types::{AddressFormat, KnownChain};
+use candid::Principal;
+
+// Memo limit for custom chains that are not in the known set
+const DEFAULT_MAX_MEMO_BYTES: usize = 256;
+
+const BASE58_ALPHABET: &str = "123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+
+pub fn known_chains() -> Vec<KnownChain> {
+    vec![
+        KnownChain { name: "REI".to_string(), address_format: AddressFormat::EvmHex, max_memo_bytes: 1024 },
+        KnownChain { name: "Polygon".to_string(), address_format: AddressFormat::EvmHex, max_memo_bytes: 1024 },
+        KnownChain { name: "Ethereum".to_string(), address_format: AddressFormat::EvmHex, max_memo_bytes: 1024 },
+        KnownChain { name: "Solana".to_string(), address_format: AddressFormat::Base58, max_memo_bytes: 566 },
+        KnownChain { name: "ICP".to_string(), address_format: AddressFormat::IcpPrincipal, max_memo_bytes: 32 },
+    ]
+}
+
+pub fn get_known_chain(name: &str) -> Option<KnownChain> {
+    known_chains().into_iter().find(|c| c.name == name)
+}
+
+/// Checks `recipient` against the address format of `chain`. Chains outside the
+/// known set are custom and are not validated.
+pub fn validate_recipient(chain: &str, recipient: &str) -> Result<(), String> {
+    let Some(known) = get_known_chain(chain) else {
+        return Ok(());
+    };
+
+    let valid = match known.address_format {
+        AddressFormat::EvmHex => is_evm_address(recipient),
+        AddressFormat::Base58 => is_base58_address(recipient),
+        AddressFormat::IcpPrincipal => Principal::from_text(recipient).is_ok(),
+    };
+
+    if valid {
+        Ok(())
+    } else {
+        Err(format!("Invalid {:?} recipient address for chain {}", known.address_format, chain))
+    }
+}
+
+fn is_evm_address(address: &str) -> bool {
+    address
+        .strip_prefix("0x")
+        .map(|hex| hex.len() == 40 && hex.chars().all(|c| c.is_ascii_hexdigit()))
+        .unwrap_or(false)
+}
+
+fn is_base58_address(address: &str) -> bool {
+    (32..=44).contains(&address.len()) && address.chars().all(|c| BASE58_ALPHABET.contains(c))
+}
+
+pub fn validate_memo(chain: &str, memo: Option<&[u8]>) -> Result<(), String> {
+    let Some(memo) = memo else {
+        return Ok(());
+    };
+
+    let limit = get_known_chain(chain)
+        .map(|c| c.max_memo_bytes as usize)
+        .unwrap_or(DEFAULT_MAX_MEMO_BYTES);
+
+    if memo.len() > limit {
+        Err(format!("Memo exceeds {} byte limit for chain {}", limit, chain))
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recipients_checked_against_chain_format() {
+        assert!(validate_recipient("Ethereum", "0x00000000000000000000000000000000000000aA").is_ok());
+        assert!(validate_recipient("Ethereum", "0x1234").is_err());
+        assert!(validate_recipient("Solana", "4Nd1mBQtrMJVYVfKf2PJy9NZUZdTAsp7D4xWLs4gDB4T").is_ok());
+        assert!(validate_recipient("Solana", "0x00000000000000000000000000000000000000aa").is_err());
+        assert!(validate_recipient("ICP", "aaaaa-aa").is_ok());
+        assert!(validate_recipient("ICP", "not a principal").is_err());
+        assert!(validate_recipient("CustomChain", "anything").is_ok());
+    }
+
+    #[test]
+    fn memo_limit_depends_on_chain() {
+        assert!(validate_memo("ICP", Some(&[0; 32])).is_ok());
+        assert!(validate_memo("ICP", Some(&[0; 33])).is_err());
+        assert!(validate_memo("CustomChain", Some(&[0; DEFAULT_MAX_MEMO_BYTES + 1])).is_err());
+        assert!(validate_memo("Ethereum", None).is_ok());
+    }
+}
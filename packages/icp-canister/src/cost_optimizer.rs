@@ -1,12 +1,26 @@
-use crate::types::{OptimizationSettings, ServiceProvider, UsageMetrics};
+use crate::types::{ChainPreference, HealthStatus, MetricsUnits, OptimizationSettings, PreferredChainMode, ServiceProvider, UsageMetrics};
 use crate::service_registry::ServiceRegistry;
-use ic_cdk::api::time;
+use crate::rng::Prng;
+use crate::clock::time;
 use std::collections::HashMap;
 
 pub struct CostOptimizer {
     settings: OptimizationSettings,
     usage_history: Vec<UsageRecord>,
     chain_costs: HashMap<String, ChainCostData>,
+    // Lifetime (success, failure) totals per provider, unaffected by history eviction
+    provider_counters: HashMap<String, (u64, u64)>,
+    // Time of each provider's most recent recorded failure
+    last_failure: HashMap<String, u64>,
+    // (computed_at, metrics) for the default metrics window
+    metrics_cache: Option<(u64, UsageMetrics)>,
+    last_rebalanced_at: Option<u64>,
+    // Payments already grouped per (provider, chain) in the current batching
+    // round, and the batch size cap for that round
+    forming_batches: HashMap<(String, String), u64>,
+    batch_max_size: u64,
+    // Named snapshots of settings an operator can switch between
+    profiles: HashMap<String, OptimizationSettings>,
 }
 
 #[derive(Clone, Debug)]
@@ -25,6 +39,8 @@ struct ChainCostData {
     volume: u64,
     success_rate: f64,
     last_updated: u64,
+    cost_sum: u128,
+    success_count: u64,
 }
 
 impl CostOptimizer {
@@ -33,6 +49,13 @@ impl CostOptimizer {
             settings,
             usage_history: Vec::new(),
             chain_costs: HashMap::new(),
+            provider_counters: HashMap::new(),
+            last_failure: HashMap::new(),
+            metrics_cache: None,
+            last_rebalanced_at: None,
+            forming_batches: HashMap::new(),
+            batch_max_size: 1,
+            profiles: HashMap::new(),
         }
     }
 
@@ -41,39 +64,138 @@ impl CostOptimizer {
         registry: &ServiceRegistry,
         chain: &str,
         amount: u64,
+        recipient: Option<&str>,
+        token: Option<&str>,
+        exclude: &[String],
     ) -> Option<String> {
-        // Get available providers for the chain
-        let providers: Vec<_> = registry
+        // Pinned recipients bypass scoring as long as the pinned provider can serve the chain
+        if let Some(pinned) = recipient.and_then(|r| self.pinned_provider(registry, r, chain, token)) {
+            if !exclude.contains(&pinned) {
+                return Some(pinned);
+            }
+        }
+
+        // Get available providers for the chain, less any the payment excludes
+        let eligible: Vec<_> = registry
             .list_providers()
             .into_iter()
-            .filter(|p| {
-                p.is_active 
-                && p.supported_chains.contains(&chain.to_string())
-                && p.cost_per_request <= self.settings.max_cost_per_transaction
-                && p.reliability_score >= self.settings.reliability_threshold
-            })
+            .filter(|p| self.is_eligible(registry, p, chain, amount) && supports_token(p, token) && !exclude.contains(&p.id))
             .collect();
 
-        if providers.is_empty() {
+        self.pick_best(registry, eligible, chain, amount)
+    }
+
+    // Chooses among eligible providers (sorted by id) the way routing does
+    fn pick_best(&self, registry: &ServiceRegistry, eligible: Vec<&ServiceProvider>, chain: &str, amount: u64) -> Option<String> {
+        if eligible.is_empty() {
             return None;
         }
 
-        // Calculate optimization score for each provider
+        // Providers still warming up only compete for a fraction of requests
+        let mut rng = Prng::new(time() ^ amount);
+        let competing: Vec<_> = eligible
+            .iter()
+            .copied()
+            .filter(|p| !self.in_warmup(registry, p) || rng.next_f64() < self.settings.warmup_traffic_fraction)
+            .collect();
+        let providers = if competing.is_empty() { eligible } else { competing };
+
+        if self.settings.normalize_scores {
+            return self.best_normalized(registry, &providers, chain, amount);
+        }
+
+        // Calculate optimization score for each provider; equal scores fall back
+        // to the provider id so the choice never depends on map iteration order
         let best_provider = providers
             .into_iter()
-            .min_by(|a, b| {
-                let a_score = self.calculate_optimization_score(a, chain, amount);
-                let b_score = self.calculate_optimization_score(b, chain, amount);
-                a_score.partial_cmp(&b_score).unwrap_or(std::cmp::Ordering::Equal)
-            });
+            .min_by_key(|p| (self.calculate_optimization_score(registry, p, chain, amount), p.id.clone()));
 
         best_provider.map(|p| p.id.clone())
     }
 
+    /// Routes a payment, applying `preferred_chain_mode` when the requested
+    /// chain is not one of the preferred chains. Only chains `chain_allowed`
+    /// accepts (the payment's recipient, memo and amount are valid there) are
+    /// considered for rerouting.
+    #[allow(clippy::too_many_arguments)]
+    pub fn plan_payment_route(
+        &self,
+        registry: &ServiceRegistry,
+        chain: &str,
+        amount: u64,
+        recipient: Option<&str>,
+        token: Option<&str>,
+        exclude: &[String],
+        chain_allowed: &dyn Fn(&str) -> bool,
+    ) -> RoutePlan {
+        let provider_id = self.optimize_payment_route(registry, chain, amount, recipient, token, exclude);
+        let mut plan = RoutePlan {
+            requested_chain: chain.to_string(),
+            chain: chain.to_string(),
+            provider_id,
+            suggested_chain: None,
+            rerouted: false,
+        };
+
+        let mode = self.settings.preferred_chain_mode;
+        let preferences = self.chain_preferences();
+        if mode == PreferredChainMode::Off || preferences.iter().any(|p| !p.is_fallback && p.name == chain) {
+            return plan;
+        }
+
+        let cost_of = |provider_id: &str| registry.get_provider(provider_id).map(|p| effective_cost(p, amount));
+        let requested_cost = plan.provider_id.as_deref().and_then(cost_of);
+        let route_on = |fallback: bool| {
+            preferences.iter().filter(|p| p.is_fallback == fallback && chain_allowed(&p.name)).find_map(|preferred| {
+                let provider_id = self.optimize_payment_route(registry, &preferred.name, amount, recipient, token, exclude)?;
+                // Moving chains is only worth it while the bridging fee does not
+                // outweigh what the preferred chain saves over the requested one
+                let bridge = self.settings.cross_chain_cost.get(&(chain.to_string(), preferred.name.clone()));
+                if let (Some(&bridge), Some(requested_cost)) = (bridge, requested_cost) {
+                    let rerouted_cost = cost_of(&provider_id).unwrap_or(u64::MAX).saturating_add(bridge);
+                    if rerouted_cost > requested_cost {
+                        return None;
+                    }
+                }
+                Some((preferred.name.clone(), provider_id))
+            })
+        };
+        // Fallback chains only come into play when no primary chain can take the
+        // payment, and a routable fallback chain is as good as any other
+        let requested_is_fallback = preferences.iter().any(|p| p.is_fallback && p.name == chain);
+        let preferred_route = match route_on(false) {
+            Some(route) => Some(route),
+            None if requested_is_fallback && plan.provider_id.is_some() => return plan,
+            None => route_on(true),
+        };
+
+        if let Some((preferred_chain, preferred_provider)) = preferred_route {
+            plan.suggested_chain = Some(preferred_chain.clone());
+            if mode == PreferredChainMode::Enforce {
+                plan.chain = preferred_chain;
+                plan.provider_id = Some(preferred_provider);
+                plan.rerouted = true;
+            }
+        }
+
+        plan
+    }
+
     pub fn suggest_chain_rebalancing(&self) -> Vec<RebalancingSuggestion> {
         let mut suggestions = Vec::new();
-        
-        for preferred_chain in &self.settings.preferred_chains {
+
+        // update_settings rejects an empty preference list; should one slip through,
+        // review every chain with recorded data rather than doing nothing
+        let preferences = self.chain_preferences();
+        let chains: Vec<String> = if preferences.is_empty() {
+            let mut recorded: Vec<String> = self.chain_costs.keys().cloned().collect();
+            recorded.sort();
+            recorded
+        } else {
+            preferences.into_iter().map(|p| p.name).collect()
+        };
+
+        for preferred_chain in &chains {
             if let Some(chain_data) = self.chain_costs.get(preferred_chain) {
                 if chain_data.success_rate < self.settings.reliability_threshold {
                     suggestions.push(RebalancingSuggestion {
@@ -89,6 +211,175 @@ impl CostOptimizer {
         suggestions
     }
 
+    /// Chains in routing priority order: primaries before fallbacks, then by
+    /// descending weight. Without `chain_preferences`, `preferred_chains` is
+    /// read as primaries of equal weight in list order.
+    pub fn chain_preferences(&self) -> Vec<ChainPreference> {
+        if self.settings.chain_preferences.is_empty() {
+            return self.settings.preferred_chains
+                .iter()
+                .map(|name| ChainPreference { name: name.clone(), weight: 1.0, is_fallback: false })
+                .collect();
+        }
+
+        let mut preferences = self.settings.chain_preferences.clone();
+        // Stable, so equal weights keep their configured order
+        preferences.sort_by(|a, b| a.is_fallback.cmp(&b.is_fallback).then(b.weight.total_cmp(&a.weight)));
+        preferences
+    }
+
+    /// Starts a settlement batching round. Until `end_batch_round`, routing
+    /// discounts providers by the batches forming in this round.
+    pub fn begin_batch_round(&mut self, batch_max_size: u64) {
+        self.forming_batches.clear();
+        self.batch_max_size = batch_max_size.max(1);
+    }
+
+    pub fn end_batch_round(&mut self) {
+        self.forming_batches.clear();
+        self.batch_max_size = 1;
+    }
+
+    /// Counts a payment routed to `provider_id` on `chain` this round.
+    pub fn note_batch_member(&mut self, provider_id: &str, chain: &str) {
+        *self.forming_batches.entry((provider_id.to_string(), chain.to_string())).or_insert(0) += 1;
+    }
+
+    // Per-payment cost once batching is taken into account. A
+    // batch_amortization_fraction share of the cost is fixed per-transaction
+    // overhead, split across the payments in the batch this one would join.
+    fn amortized_cost(&self, provider_id: &str, chain: &str, cost: u64) -> u64 {
+        let fraction = self.settings.batch_amortization_fraction.clamp(0.0, 1.0);
+        if fraction == 0.0 || self.batch_max_size <= 1 {
+            return cost;
+        }
+
+        let grouped = self.forming_batches
+            .get(&(provider_id.to_string(), chain.to_string()))
+            .copied()
+            .unwrap_or(0);
+        // A full batch settles on its own, so only the open batch is joined
+        let forming = grouped % self.batch_max_size;
+        let share = (1.0 - fraction) + fraction / (forming + 1) as f64;
+        (cost as f64 * share).round() as u64
+    }
+
+    /// Acts on rebalancing suggestions when `auto_optimization_enabled` is set,
+    /// at most once per `rebalance_frequency`: each struggling preferred chain is
+    /// moved behind its healthier alternative in `preferred_chains`. Returns the
+    /// shifts applied.
+    pub fn apply_rebalancing(&mut self) -> Vec<RebalancingSuggestion> {
+        let now = time();
+        let frequency = self.settings.rebalance_frequency.saturating_mul(1_000_000_000);
+        if !self.settings.auto_optimization_enabled
+            || self.last_rebalanced_at.is_some_and(|at| now.saturating_sub(at) < frequency)
+        {
+            return Vec::new();
+        }
+        self.last_rebalanced_at = Some(now);
+
+        let mut applied = Vec::new();
+        for suggestion in self.suggest_chain_rebalancing() {
+            let from_rate = self.chain_costs.get(&suggestion.from_chain).map(|d| d.success_rate);
+            let to_rate = self.chain_costs.get(&suggestion.to_chain).map(|d| d.success_rate);
+            // Only shift toward a chain that has actually done better
+            let healthier = matches!((from_rate, to_rate), (Some(from), Some(to)) if to > from);
+            if !healthier {
+                continue;
+            }
+
+            if !self.settings.chain_preferences.is_empty() {
+                if self.demote_chain_preference(&suggestion.from_chain, &suggestion.to_chain) {
+                    applied.push(suggestion);
+                }
+                continue;
+            }
+
+            let chains = &mut self.settings.preferred_chains;
+            let Some(from_index) = chains.iter().position(|c| *c == suggestion.from_chain) else {
+                continue;
+            };
+            chains.remove(from_index);
+            match chains.iter().position(|c| *c == suggestion.to_chain) {
+                Some(to_index) if to_index >= from_index => chains.insert(to_index + 1, suggestion.from_chain.clone()),
+                // Already ahead of the struggling chain; put the struggling chain back
+                Some(_) => chains.insert(from_index, suggestion.from_chain.clone()),
+                None => {
+                    chains.insert(from_index, suggestion.to_chain.clone());
+                    chains.insert(from_index + 1, suggestion.from_chain.clone());
+                }
+            }
+            applied.push(suggestion);
+        }
+
+        applied
+    }
+
+    // Typed counterpart of the preferred_chains reordering: the struggling chain
+    // trades rank (weight and fallback flag) with the healthier one if that one
+    // currently ranks below it, or hands its rank to it if it is not configured.
+    fn demote_chain_preference(&mut self, from_chain: &str, to_chain: &str) -> bool {
+        let preferences = &mut self.settings.chain_preferences;
+        let Some(from_index) = preferences.iter().position(|p| p.name == from_chain) else {
+            return false;
+        };
+        let rank = |p: &ChainPreference| (p.is_fallback, -p.weight);
+
+        match preferences.iter().position(|p| p.name == to_chain) {
+            Some(to_index) => {
+                if rank(&preferences[to_index]) <= rank(&preferences[from_index]) {
+                    return false;
+                }
+                let from = preferences[from_index].clone();
+                let to = preferences[to_index].clone();
+                preferences[from_index] = ChainPreference { name: from.name, weight: to.weight, is_fallback: to.is_fallback };
+                preferences[to_index] = ChainPreference { name: to.name, weight: from.weight, is_fallback: from.is_fallback };
+            }
+            None => {
+                let from = preferences[from_index].clone();
+                // Inserted ahead, so it wins the weight tie
+                preferences.insert(from_index, ChainPreference { name: to_chain.to_string(), ..from });
+            }
+        }
+        true
+    }
+
+    /// Share of recorded traffic per chain now, and after every current
+    /// rebalancing suggestion moved its source chain's share to the target.
+    pub fn preview_rebalancing(&self) -> RebalancingPreview {
+        let mut counts: HashMap<String, u64> = HashMap::new();
+        for record in &self.usage_history {
+            *counts.entry(record.chain.clone()).or_insert(0) += 1;
+        }
+        let total = self.usage_history.len() as f64;
+        let current: HashMap<String, f64> = counts
+            .into_iter()
+            .map(|(chain, count)| (chain, count as f64 / total))
+            .collect();
+
+        let suggestions = self.suggest_chain_rebalancing();
+        let mut projected = current.clone();
+        for suggestion in &suggestions {
+            let moved = projected.insert(suggestion.from_chain.clone(), 0.0).unwrap_or(0.0);
+            *projected.entry(suggestion.to_chain.clone()).or_insert(0.0) += moved;
+        }
+
+        let sorted = |shares: HashMap<String, f64>| {
+            let mut shares: Vec<(String, f64)> = shares.into_iter().collect();
+            shares.sort_by(|a, b| a.0.cmp(&b.0));
+            shares
+        };
+        RebalancingPreview {
+            current_distribution: sorted(current),
+            projected_distribution: sorted(projected),
+            total_projected_savings: suggestions.iter().map(|s| s.potential_savings).sum(),
+            suggestions,
+        }
+    }
+
+    /// Records a settlement for cost and usage metrics. With `freeze_standing`
+    /// (the provider is in maintenance) its success/failure counters and last
+    /// failure time are left as they were, so it returns at the same standing.
     pub fn record_usage(
         &mut self,
         chain: &str,
@@ -96,6 +387,7 @@ impl CostOptimizer {
         cost: u64,
         success: bool,
         response_time: f64,
+        freeze_standing: bool,
     ) {
         let record = UsageRecord {
             timestamp: time(),
@@ -108,24 +400,116 @@ impl CostOptimizer {
 
         self.usage_history.push(record);
         self.update_chain_costs(chain, cost, success);
+        self.metrics_cache = None;
 
         // Keep only last 1000 records
         if self.usage_history.len() > 1000 {
             self.usage_history.remove(0);
         }
+
+        if freeze_standing {
+            return;
+        }
+        let counters = self.provider_counters.entry(provider_id.to_string()).or_insert((0, 0));
+        if success {
+            counters.0 = counters.0.saturating_add(1);
+        } else {
+            counters.1 = counters.1.saturating_add(1);
+            self.last_failure.insert(provider_id.to_string(), time());
+        }
+    }
+
+    pub fn get_usage_metrics(&self, time_window_seconds: u64) -> UsageMetrics {
+        if time_window_seconds == self.settings.default_metrics_window_seconds {
+            if let Some(cached) = self.cached_metrics() {
+                return cached;
+            }
+        }
+
+        let recent_records = self.records_within(time_window_seconds);
+        self.compute_metrics(&recent_records)
+    }
+
+    /// Recomputes the default-window metrics. Query calls can't persist state,
+    /// so the cache is refreshed from update context (the processing timer) and
+    /// served to queries until the TTL lapses or new usage is recorded.
+    pub fn refresh_metrics_cache(&mut self) {
+        if self.settings.metrics_cache_ttl_seconds == 0 {
+            self.metrics_cache = None;
+            return;
+        }
+
+        let recent_records = self.records_within(self.settings.default_metrics_window_seconds);
+        let metrics = self.compute_metrics(&recent_records);
+        self.metrics_cache = Some((time(), metrics));
+    }
+
+    fn cached_metrics(&self) -> Option<UsageMetrics> {
+        let ttl = self.settings.metrics_cache_ttl_seconds.saturating_mul(1_000_000_000);
+        self.metrics_cache
+            .as_ref()
+            .filter(|(computed_at, _)| time().saturating_sub(*computed_at) < ttl)
+            .map(|(_, metrics)| metrics.clone())
+    }
+
+    pub fn get_metrics_by_chain(&self, time_window_seconds: u64) -> Vec<(String, UsageMetrics)> {
+        let mut by_chain: HashMap<&str, Vec<&UsageRecord>> = HashMap::new();
+        for record in self.records_within(time_window_seconds) {
+            by_chain.entry(record.chain.as_str()).or_default().push(record);
+        }
+
+        let mut metrics: Vec<(String, UsageMetrics)> = by_chain
+            .into_iter()
+            .map(|(chain, records)| (chain.to_string(), self.compute_metrics(&records)))
+            .collect();
+        metrics.sort_by(|a, b| a.0.cmp(&b.0));
+        metrics
+    }
+
+    /// CSV (header included, oldest first) of up to `limit` usage records in the
+    /// window starting at `offset`, stopping early rather than let the output
+    /// pass `max_bytes` (at least one record is always written). Returns the
+    /// offset of the first record left out, if any.
+    pub fn export_usage_csv(&self, time_window_seconds: u64, offset: usize, limit: usize, max_bytes: usize) -> (String, Option<u64>) {
+        let records = self.records_within(time_window_seconds);
+        let mut csv = String::from("timestamp,chain,provider_id,cost,success,response_time\n");
+        let mut next = offset;
+        for record in records.iter().skip(offset).take(limit) {
+            let row = format!(
+                "{},{},{},{},{},{}\n",
+                record.timestamp,
+                csv_field(&record.chain),
+                csv_field(&record.provider_id),
+                record.cost,
+                record.success,
+                record.response_time
+            );
+            if next > offset && csv.len() + row.len() > max_bytes {
+                break;
+            }
+            csv.push_str(&row);
+            next += 1;
+        }
+
+        (csv, (next < records.len()).then_some(next as u64))
     }
 
-    pub fn get_usage_metrics(&self, time_window: u64) -> UsageMetrics {
+    fn records_within(&self, time_window_seconds: u64) -> Vec<&UsageRecord> {
         let current_time = time();
-        let recent_records: Vec<_> = self.usage_history
+        let time_window = time_window_seconds.saturating_mul(1_000_000_000);
+        self.usage_history
             .iter()
-            .filter(|r| current_time - r.timestamp <= time_window)
-            .collect();
+            .filter(|r| current_time.saturating_sub(r.timestamp) <= time_window)
+            .collect()
+    }
 
+    fn compute_metrics(&self, recent_records: &[&UsageRecord]) -> UsageMetrics {
         let total_requests = recent_records.len() as u64;
         let successful_payments = recent_records.iter().filter(|r| r.success).count() as u64;
         let failed_payments = total_requests - successful_payments;
-        let total_volume = recent_records.iter().map(|r| r.cost).sum();
+        // Sum in u128 so a high-volume window saturates instead of overflowing
+        let volume_sum: u128 = recent_records.iter().map(|r| r.cost as u128).sum();
+        let total_volume = u64::try_from(volume_sum).unwrap_or(u64::MAX);
         
         let average_response_time = if !recent_records.is_empty() {
             recent_records.iter().map(|r| r.response_time).sum::<f64>() / recent_records.len() as f64
@@ -133,8 +517,22 @@ impl CostOptimizer {
             0.0
         };
 
-        let cost_efficiency = if total_requests > 0 {
-            successful_payments as f64 / total_volume as f64 * 1000000.0 // per million wei
+        // Of the successful payments on chains with a settlement target, the share
+        // that settled within it
+        let targeted: Vec<bool> = recent_records
+            .iter()
+            .filter(|r| r.success)
+            .filter_map(|r| self.settings.target_settlement_seconds.get(&r.chain).map(|&target| r.response_time <= target))
+            .collect();
+        let on_time_rate = if !targeted.is_empty() {
+            targeted.iter().filter(|&&on_time| on_time).count() as f64 / targeted.len() as f64
+        } else {
+            0.0
+        };
+
+        let scale = self.settings.cost_efficiency_scale;
+        let cost_efficiency = if volume_sum > 0 {
+            successful_payments as f64 / volume_sum as f64 * scale
         } else {
             0.0
         };
@@ -146,43 +544,424 @@ impl CostOptimizer {
             total_volume,
             average_response_time,
             cost_efficiency,
+            on_time_rate,
+            units: MetricsUnits {
+                volume_unit: "wei".to_string(),
+                response_time_unit: "seconds".to_string(),
+                cost_efficiency_unit: format!("successful payments per {} wei", scale),
+                cost_efficiency_scale: scale,
+            },
+        }
+    }
+
+    pub fn stale_chains(&self, max_age_seconds: u64) -> Vec<String> {
+        let current_time = time();
+        let max_age = max_age_seconds.saturating_mul(1_000_000_000);
+
+        let mut chains: Vec<String> = self.chain_costs
+            .iter()
+            .filter(|(_, data)| current_time.saturating_sub(data.last_updated) > max_age)
+            .map(|(chain, _)| chain.clone())
+            .collect();
+        chains.sort();
+        chains
+    }
+
+    /// Clears a chain's cost data and usage history, returning whether there
+    /// was any. Resetting a chain with nothing recorded is a no-op.
+    pub fn reset_chain_data(&mut self, chain: &str) -> bool {
+        let had_costs = self.chain_costs.remove(chain).is_some();
+        let recorded = self.usage_history.len();
+        self.usage_history.retain(|r| r.chain != chain);
+        let had_data = had_costs || self.usage_history.len() != recorded;
+        if had_data {
+            self.metrics_cache = None;
+        }
+        had_data
+    }
+
+    pub fn get_provider_counters(&self, provider_id: &str) -> (u64, u64) {
+        self.provider_counters.get(provider_id).copied().unwrap_or((0, 0))
+    }
+
+    /// Providers ranked by cost efficiency, best first:
+    ///
+    /// `efficiency = success_rate / cost_per_request * cost_efficiency_scale`
+    ///
+    /// i.e. expected successful payments per `cost_efficiency_scale` wei spent.
+    /// `success_rate` is the provider's lifetime success ratio smoothed toward
+    /// `success_rate_prior` (same prior as chain success rates); a zero cost is
+    /// treated as one wei.
+    pub fn provider_efficiency_ranking(&self, registry: &ServiceRegistry) -> Vec<(String, f64)> {
+        let prior_weight = self.settings.success_rate_prior_weight.max(0.0);
+        let mut ranking: Vec<(String, f64)> = registry
+            .list_providers()
+            .into_iter()
+            .map(|provider| {
+                let (successes, failures) = self.get_provider_counters(&provider.id);
+                let samples = successes.saturating_add(failures) as f64;
+                let success_rate = if samples + prior_weight > 0.0 {
+                    (successes as f64 + self.settings.success_rate_prior * prior_weight) / (samples + prior_weight)
+                } else {
+                    self.settings.success_rate_prior
+                };
+                let cost = provider.cost_per_request.max(1) as f64;
+                (provider.id.clone(), success_rate / cost * self.settings.cost_efficiency_scale)
+            })
+            .collect();
+
+        ranking.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+        ranking
+    }
+
+    /// Stores the current settings under `name`, replacing any profile of that name.
+    pub fn save_settings_profile(&mut self, name: &str) -> Result<(), String> {
+        if name.is_empty() {
+            return Err("Profile name must not be empty".to_string());
+        }
+        self.profiles.insert(name.to_string(), self.settings.clone());
+        Ok(())
+    }
+
+    /// Applies a saved profile through the same validation as `update_settings`.
+    pub fn load_settings_profile(&mut self, name: &str) -> Result<(), String> {
+        let settings = self.profiles.get(name).cloned().ok_or("Settings profile not found")?;
+        self.update_settings(settings)
+    }
+
+    pub fn list_settings_profiles(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.profiles.keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    /// Hands `old_id`'s recipient pins, success/failure counters and last failure
+    /// time to its successor `new_id`.
+    pub fn transfer_provider(&mut self, old_id: &str, new_id: &str) {
+        for pinned in self.settings.recipient_provider_pin.values_mut() {
+            if pinned == old_id {
+                *pinned = new_id.to_string();
+            }
+        }
+        if let Some(counters) = self.provider_counters.remove(old_id) {
+            self.provider_counters.insert(new_id.to_string(), counters);
+        }
+        if let Some(failed_at) = self.last_failure.remove(old_id) {
+            self.last_failure.insert(new_id.to_string(), failed_at);
         }
     }
 
-    pub fn update_settings(&mut self, settings: OptimizationSettings) {
+    pub fn settings(&self) -> &OptimizationSettings {
+        &self.settings
+    }
+
+    pub fn update_settings(&mut self, settings: OptimizationSettings) -> Result<(), String> {
+        if settings.preferred_chains.is_empty() && settings.chain_preferences.is_empty() {
+            return Err("preferred_chains or chain_preferences must not be empty".to_string());
+        }
+        if settings.chain_preferences.iter().any(|p| !p.weight.is_finite()) {
+            return Err("chain_preferences weights must be finite".to_string());
+        }
+        if let Some(alpha) = settings.chain_cost_learning_rate {
+            if !(alpha > 0.0 && alpha <= 1.0) {
+                return Err("chain_cost_learning_rate must be in (0, 1]".to_string());
+            }
+        }
+
         self.settings = settings;
+        self.metrics_cache = None;
+        Ok(())
     }
 
-    fn calculate_optimization_score(&self, provider: &ServiceProvider, chain: &str, amount: u64) -> f64 {
-        let cost_score = provider.cost_per_request as f64 / amount as f64;
-        let reliability_score = 1.0 - provider.reliability_score;
-        
-        let historical_score = if let Some(chain_data) = self.chain_costs.get(chain) {
-            1.0 - chain_data.success_rate
+    // A pin is honoured only while the provider could take the payment at all;
+    // otherwise the recipient is routed like any other
+    fn pinned_provider(&self, registry: &ServiceRegistry, recipient: &str, chain: &str, token: Option<&str>) -> Option<String> {
+        let provider_id = self.settings.recipient_provider_pin.get(recipient)?;
+        registry
+            .get_provider(provider_id)
+            .filter(|p| {
+                p.is_active
+                    && !registry.is_blocklisted(&p.id)
+                    && !registry.in_maintenance(&p.id)
+                    && registry.health_status(p, time(), &self.settings) != HealthStatus::Unresponsive
+                    && registry.has_quota_remaining(&p.id)
+                    && p.supported_chains.contains(&chain.to_string())
+                    && supports_token(p, token)
+            })
+            .map(|p| p.id.clone())
+    }
+
+    /// Scores of every provider serving `chain`, best first.
+    pub fn candidate_scores(&self, registry: &ServiceRegistry, chain: &str, amount: u64) -> Vec<ProviderScore> {
+        let mut scores: Vec<ProviderScore> = registry
+            .list_providers()
+            .into_iter()
+            .filter(|p| p.supported_chains.iter().any(|c| c == chain))
+            .map(|p| self.score_breakdown(registry, p, chain, amount))
+            .collect();
+        scores.sort_by(|a, b| b.eligible.cmp(&a.eligible).then(a.total_score_fixed.cmp(&b.total_score_fixed)));
+        scores
+    }
+
+    pub fn compare_providers(
+        &self,
+        registry: &ServiceRegistry,
+        a: &str,
+        b: &str,
+        chain: &str,
+        amount: u64,
+    ) -> Result<ProviderComparison, String> {
+        let provider_a = registry.get_provider(a).ok_or("Provider A not found")?;
+        let provider_b = registry.get_provider(b).ok_or("Provider B not found")?;
+
+        // The winner is whoever routing would pick were these the only two
+        // candidates, including id tie-breaks, normalization and warmup
+        let mut eligible: Vec<_> = [provider_a, provider_b]
+            .into_iter()
+            .filter(|p| self.is_eligible(registry, p, chain, amount))
+            .collect();
+        eligible.sort_by(|x, y| x.id.cmp(&y.id));
+        eligible.dedup_by(|x, y| x.id == y.id);
+        let winner = self.pick_best(registry, eligible, chain, amount);
+
+        let a = self.score_breakdown(registry, provider_a, chain, amount);
+        let b = self.score_breakdown(registry, provider_b, chain, amount);
+
+        Ok(ProviderComparison {
+            chain: chain.to_string(),
+            amount,
+            a,
+            b,
+            winner,
+        })
+    }
+
+    /// Coverage for each chain: active providers, cheapest base cost, observed
+    /// success rate, and whether any provider currently passes routing thresholds.
+    pub fn supported_chains_status(&self, registry: &ServiceRegistry, chains: &[String]) -> Vec<ChainStatus> {
+        chains
+            .iter()
+            .map(|chain| {
+                let active: Vec<_> = registry
+                    .list_providers()
+                    .into_iter()
+                    .filter(|p| p.is_active && p.supported_chains.contains(chain))
+                    .collect();
+
+                ChainStatus {
+                    chain: chain.clone(),
+                    active_providers: active.len() as u64,
+                    cheapest_cost: active.iter().map(|p| p.cost_per_request).min(),
+                    avg_success_rate: self.chain_costs.get(chain).map(|data| data.success_rate),
+                    routable: active.iter().any(|p| self.is_eligible(registry, p, chain, 0)),
+                }
+            })
+            .collect()
+    }
+
+    fn in_warmup(&self, registry: &ServiceRegistry, provider: &ServiceProvider) -> bool {
+        let warmup = self.settings.warmup_period_seconds.saturating_mul(1_000_000_000);
+        registry
+            .registered_at(&provider.id)
+            .is_some_and(|registered_at| time().saturating_sub(registered_at) < warmup)
+    }
+
+    /// Fully-resolved cost the router uses for a payment of `amount` through
+    /// `provider` on `chain`, or `None` if the provider can't serve the chain.
+    pub fn get_effective_cost(&self, provider: &ServiceProvider, chain: &str, amount: u64) -> Option<u64> {
+        if !provider.supported_chains.contains(&chain.to_string()) {
+            return None;
+        }
+        Some(effective_cost(provider, amount))
+    }
+
+    fn is_eligible(&self, registry: &ServiceRegistry, provider: &ServiceProvider, chain: &str, amount: u64) -> bool {
+        provider.is_active
+            && !registry.is_blocklisted(&provider.id)
+            && !registry.in_maintenance(&provider.id)
+            && registry.health_status(provider, time(), &self.settings) != HealthStatus::Unresponsive
+            && registry.has_quota_remaining(&provider.id)
+            && provider.supported_chains.contains(&chain.to_string())
+            && effective_cost(provider, amount) <= self.settings.max_cost_per_transaction
+            && provider.reliability_score >= self.settings.reliability_threshold
+    }
+
+    fn calculate_optimization_score(&self, registry: &ServiceRegistry, provider: &ServiceProvider, chain: &str, amount: u64) -> u64 {
+        self.score_breakdown(registry, provider, chain, amount).total_score_fixed
+    }
+
+    // Reliability used for scoring. Until a provider has min_samples_for_full_trust
+    // recorded outcomes, its reported score is blended toward success_rate_prior in
+    // proportion to the samples seen, so a few lucky results cannot outrank history.
+    fn trusted_reliability(&self, provider: &ServiceProvider) -> f64 {
+        let min_samples = self.settings.min_samples_for_full_trust;
+        if min_samples == 0 {
+            return provider.reliability_score;
+        }
+
+        let (successes, failures) = self.get_provider_counters(&provider.id);
+        let trust = (successes.saturating_add(failures) as f64 / min_samples as f64).min(1.0);
+        trust * provider.reliability_score + (1.0 - trust) * self.settings.success_rate_prior
+    }
+
+    // Fixed-point (cost, reliability, historical) sub-scores; lower is better
+    fn sub_scores(&self, provider: &ServiceProvider, chain: &str, amount: u64) -> [u128; 3] {
+        let scale = SCORE_SCALE as u128;
+        let scored_cost = self.amortized_cost(&provider.id, chain, effective_cost(provider, amount));
+        let cost_fixed = scored_cost as u128 * scale / amount.max(1) as u128;
+        let reliability_fixed = scale - to_fixed(self.trusted_reliability(provider));
+
+        let historical_fixed = if let Some(chain_data) = self.chain_costs.get(chain) {
+            scale - to_fixed(chain_data.success_rate)
         } else {
-            0.5 // Default for new chains
+            scale / 2 // Default for new chains
         };
 
-        // Prefer lower costs, higher reliability, better historical performance
-        (cost_score * 0.4) + (reliability_score * 0.3) + (historical_score * 0.3)
+        [cost_fixed, reliability_fixed, historical_fixed]
+    }
+
+    // With normalize_scores, each sub-score is min-max scaled to [0, 1] across
+    // the candidates before weighting, so ranking reflects relative standing and
+    // one extreme value cannot swamp the other dimensions.
+    fn best_normalized(&self, registry: &ServiceRegistry, providers: &[&ServiceProvider], chain: &str, amount: u64) -> Option<String> {
+        let raw: Vec<[u128; 3]> = providers.iter().map(|p| self.sub_scores(p, chain, amount)).collect();
+        let scale = SCORE_SCALE as u128;
+
+        let mut bounds = [(u128::MAX, 0u128); 3];
+        for scores in &raw {
+            for (bound, &score) in bounds.iter_mut().zip(scores) {
+                *bound = (bound.0.min(score), bound.1.max(score));
+            }
+        }
+
+        providers
+            .iter()
+            .zip(&raw)
+            .map(|(provider, scores)| {
+                let mut normalized = [0u128; 3];
+                for ((value, &(min, max)), &score) in normalized.iter_mut().zip(&bounds).zip(scores) {
+                    // A dimension where every candidate ties does not separate them
+                    *value = if max > min { (score - min) * scale / (max - min) } else { 0 };
+                }
+                (weighted_total(normalized) + self.penalties(registry, provider, chain, amount), provider.id.clone())
+            })
+            .min()
+            .map(|(_, id)| id)
+    }
+
+    // Score added on top of the weighted sub-scores: degraded providers stay
+    // routable but rank behind comparable healthy ones, and a provider that just
+    // failed is pushed back by recent_failure_penalty, fading linearly to zero
+    // over recent_failure_window_seconds. With capacity_weight set, a provider is
+    // also penalised by how much of its capacity the payment (plus the batch it
+    // would join) takes up, so large volume favours providers that can absorb it.
+    // Routing on data older than stale_data_seconds is penalised too.
+    fn penalties(&self, registry: &ServiceRegistry, provider: &ServiceProvider, chain: &str, amount: u64) -> u128 {
+        let now = time();
+        let health = match registry.health_status(provider, now, &self.settings) {
+            HealthStatus::Degraded => DEGRADED_PENALTY,
+            _ => 0,
+        };
+
+        let window = self.settings.recent_failure_window_seconds.saturating_mul(1_000_000_000);
+        let recency = match self.last_failure.get(&provider.id) {
+            Some(&failed_at) if now.saturating_sub(failed_at) < window => {
+                let remaining = 1.0 - now.saturating_sub(failed_at) as f64 / window as f64;
+                let penalty = self.settings.recent_failure_penalty.max(0.0) * remaining;
+                (penalty * SCORE_SCALE as f64).round() as u128
+            }
+            _ => 0,
+        };
+
+        let capacity = match provider.capacity {
+            Some(capacity) if self.settings.capacity_weight > 0.0 => {
+                let grouped = self.forming_batches
+                    .get(&(provider.id.clone(), chain.to_string()))
+                    .map_or(0, |&count| count % self.batch_max_size.max(1));
+                let volume = amount.saturating_mul(grouped.saturating_add(1));
+                let load = volume as f64 / capacity.max(1) as f64;
+                (self.settings.capacity_weight * load.min(1.0) * SCORE_SCALE as f64).round() as u128
+            }
+            _ => 0,
+        };
+
+        // Stale provider outcomes or chain cost data each add stale_data_penalty
+        let stale_after = self.settings.stale_data_seconds.saturating_mul(1_000_000_000);
+        let freshness = if stale_after > 0 {
+            let provider_stale = now.saturating_sub(registry.last_seen(provider, now)) > stale_after;
+            let chain_stale = self.chain_costs
+                .get(chain)
+                .is_some_and(|data| now.saturating_sub(data.last_updated) > stale_after);
+            let stale_count = provider_stale as u128 + chain_stale as u128;
+            stale_count * (self.settings.stale_data_penalty.max(0.0) * SCORE_SCALE as f64).round() as u128
+        } else {
+            0
+        };
+
+        health + recency + capacity + freshness
+    }
+
+    fn score_breakdown(&self, registry: &ServiceRegistry, provider: &ServiceProvider, chain: &str, amount: u64) -> ProviderScore {
+        let effective_cost = effective_cost(provider, amount);
+        let [cost_fixed, reliability_fixed, historical_fixed] = self.sub_scores(provider, chain, amount);
+        let total_fixed = weighted_total([cost_fixed, reliability_fixed, historical_fixed])
+            + self.penalties(registry, provider, chain, amount);
+        let total_score_fixed = u64::try_from(total_fixed).unwrap_or(u64::MAX);
+
+        ProviderScore {
+            provider_id: provider.id.clone(),
+            cost_per_request: provider.cost_per_request,
+            effective_cost,
+            reliability: provider.reliability_score,
+            eligible: self.is_eligible(registry, provider, chain, amount),
+            cost_score: from_fixed(cost_fixed),
+            reliability_score: from_fixed(reliability_fixed),
+            historical_score: from_fixed(historical_fixed),
+            total_score: from_fixed(total_fixed),
+            total_score_fixed,
+        }
     }
 
     fn update_chain_costs(&mut self, chain: &str, cost: u64, success: bool) {
         let current_time = time();
         
         let chain_data = self.chain_costs.entry(chain.to_string()).or_insert(ChainCostData {
-            average_cost: cost as f64,
+            average_cost: 0.0,
             volume: 0,
-            success_rate: if success { 1.0 } else { 0.0 },
+            success_rate: 0.0,
             last_updated: current_time,
+            cost_sum: 0,
+            success_count: 0,
         });
 
-        // Update running averages
-        chain_data.volume += 1;
-        chain_data.average_cost = ((chain_data.average_cost * (chain_data.volume - 1) as f64) + cost as f64) / chain_data.volume as f64;
-        
-        let success_value = if success { 1.0 } else { 0.0 };
-        chain_data.success_rate = ((chain_data.success_rate * (chain_data.volume - 1) as f64) + success_value) / chain_data.volume as f64;
+        // Derive the averages from exact totals; the success rate is additionally smoothed
+        // toward the configured prior so cold-start chains are not judged on one sample
+        chain_data.volume = chain_data.volume.saturating_add(1);
+        chain_data.cost_sum = chain_data.cost_sum.saturating_add(cost as u128);
+        if success {
+            chain_data.success_count = chain_data.success_count.saturating_add(1);
+        }
+
+        match self.settings.chain_cost_learning_rate {
+            // Exponential moving average: recent samples dominate so the optimizer tracks
+            // regime changes; the first sample seeds the cost and the prior seeds the rate
+            Some(alpha) => {
+                let outcome = if success { 1.0 } else { 0.0 };
+                if chain_data.volume == 1 {
+                    chain_data.average_cost = cost as f64;
+                    chain_data.success_rate = self.settings.success_rate_prior;
+                } else {
+                    chain_data.average_cost += alpha * (cost as f64 - chain_data.average_cost);
+                }
+                chain_data.success_rate += alpha * (outcome - chain_data.success_rate);
+            }
+            None => {
+                chain_data.average_cost = chain_data.cost_sum as f64 / chain_data.volume as f64;
+                let prior_weight = self.settings.success_rate_prior_weight.max(0.0);
+                chain_data.success_rate = (chain_data.success_count as f64 + self.settings.success_rate_prior * prior_weight)
+                    / (chain_data.volume as f64 + prior_weight);
+            }
+        }
         
         chain_data.last_updated = current_time;
     }
@@ -221,3 +1000,795 @@ pub struct RebalancingSuggestion {
     pub reason: String,
     pub potential_savings: f64,
 }
+
+#[derive(Clone, Debug, candid::CandidType, serde::Serialize, serde::Deserialize)]
+pub struct RebalancingPreview {
+    pub current_distribution: Vec<(String, f64)>,
+    pub projected_distribution: Vec<(String, f64)>,
+    pub total_projected_savings: f64,
+    pub suggestions: Vec<RebalancingSuggestion>,
+}
+
+/// Per-request cost for `amount`: the highest tier whose `min_amount` the amount
+/// reaches, falling back to `cost_per_request` below every tier.
+pub fn effective_cost(provider: &ServiceProvider, amount: u64) -> u64 {
+    provider.cost_tiers
+        .iter()
+        .flatten()
+        .filter(|tier| amount >= tier.min_amount)
+        .max_by_key(|tier| tier.min_amount)
+        .map(|tier| tier.cost_per_request)
+        .unwrap_or(provider.cost_per_request)
+}
+
+/// Whether the provider can settle `token`; a payment without a token, or a
+/// provider without a token list, imposes no restriction.
+pub fn supports_token(provider: &ServiceProvider, token: Option<&str>) -> bool {
+    let supported = provider.supported_tokens.as_deref().unwrap_or_default();
+    token.is_none_or(|token| supported.is_empty() || supported.iter().any(|t| t == token))
+}
+
+/// Fixed-point units per 1.0 in routing scores (six decimal places). Scores are
+/// compared as integers so routing is reproducible on every replica.
+pub const SCORE_SCALE: u64 = 1_000_000;
+
+// RFC 4180 quoting for fields containing a delimiter, quote or line break
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+// Prefer lower costs, higher reliability, better historical performance
+// (weights 0.4 / 0.3 / 0.3, applied as integer tenths)
+fn weighted_total([cost, reliability, historical]: [u128; 3]) -> u128 {
+    (cost * 4 + reliability * 3 + historical * 3) / 10
+}
+
+// Added to a degraded provider's total score (0.2 in score units)
+const DEGRADED_PENALTY: u128 = SCORE_SCALE as u128 / 5;
+
+// Ratio in [0, 1] to fixed point
+fn to_fixed(ratio: f64) -> u128 {
+    (ratio.clamp(0.0, 1.0) * SCORE_SCALE as f64).round() as u128
+}
+
+fn from_fixed(value: u128) -> f64 {
+    value as f64 / SCORE_SCALE as f64
+}
+
+/// Sub-scores behind a routing decision; lower `total_score` is better.
+#[derive(Clone, Debug, candid::CandidType, serde::Serialize, serde::Deserialize)]
+pub struct ProviderScore {
+    pub provider_id: String,
+    pub cost_per_request: u64,
+    pub effective_cost: u64,
+    pub reliability: f64,
+    pub eligible: bool,
+    pub cost_score: f64,
+    pub reliability_score: f64,
+    pub historical_score: f64,
+    pub total_score: f64,
+    // Authoritative score in SCORE_SCALE units; the f64 fields are for display
+    pub total_score_fixed: u64,
+}
+
+#[derive(Clone, Debug, candid::CandidType, serde::Serialize, serde::Deserialize)]
+pub struct ProviderComparison {
+    pub chain: String,
+    pub amount: u64,
+    pub a: ProviderScore,
+    pub b: ProviderScore,
+    pub winner: Option<String>,
+}
+
+#[derive(Clone, Debug, candid::CandidType, serde::Serialize, serde::Deserialize)]
+pub struct ChainStatus {
+    pub chain: String,
+    pub active_providers: u64,
+    pub cheapest_cost: Option<u64>,
+    pub avg_success_rate: Option<f64>,
+    pub routable: bool,
+}
+
+#[derive(Clone, Debug, candid::CandidType, serde::Serialize, serde::Deserialize)]
+pub struct RoutePlan {
+    pub requested_chain: String,
+    pub chain: String,
+    pub provider_id: Option<String>,
+    pub suggested_chain: Option<String>,
+    pub rerouted: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock;
+    use crate::types::{CostTier, Quota};
+
+    fn registry_with(providers: &[ServiceProvider]) -> ServiceRegistry {
+        let mut registry = ServiceRegistry::new();
+        for provider in providers {
+            registry.register_provider(provider.clone(), None).unwrap();
+        }
+        registry
+    }
+
+    fn pinned_optimizer(recipient: &str, provider_id: &str) -> CostOptimizer {
+        let mut settings = OptimizationSettings::default();
+        settings.recipient_provider_pin.insert(recipient.to_string(), provider_id.to_string());
+        CostOptimizer::new(settings)
+    }
+
+    #[test]
+    fn csv_export_quotes_commas_and_quotes() {
+        let mut optimizer = CostOptimizer::new(OptimizationSettings::default());
+        optimizer.record_usage("eth,mainnet", "say \"hi\"", 100, true, 0.5, false);
+
+        let (csv, next) = optimizer.export_usage_csv(3600, 0, 10, usize::MAX);
+        assert_eq!(csv.lines().nth(1), Some("1000000000,\"eth,mainnet\",\"say \"\"hi\"\"\",100,true,0.5"));
+        assert_eq!(next, None);
+    }
+
+    #[test]
+    fn csv_export_pages_and_truncates_with_continuation() {
+        let mut optimizer = CostOptimizer::new(OptimizationSettings::default());
+        for n in 0..5 {
+            optimizer.record_usage("ethereum", &format!("p{}", n), 100, true, 0.5, false);
+        }
+        let providers = |csv: &str| -> Vec<String> {
+            csv.lines().skip(1).map(|line| line.split(',').nth(2).unwrap().to_string()).collect()
+        };
+
+        let (csv, next) = optimizer.export_usage_csv(3600, 1, 2, usize::MAX);
+        assert_eq!((providers(&csv), next), (vec!["p1".to_string(), "p2".to_string()], Some(3)));
+        let (csv, next) = optimizer.export_usage_csv(3600, 3, 10, usize::MAX);
+        assert_eq!((providers(&csv), next), (vec!["p3".to_string(), "p4".to_string()], None));
+
+        let (full, _) = optimizer.export_usage_csv(3600, 0, 10, usize::MAX);
+        let header_and_two_rows: usize = full.split_inclusive('\n').take(3).map(str::len).sum();
+        let (csv, next) = optimizer.export_usage_csv(3600, 0, 10, header_and_two_rows + 1);
+        assert_eq!((providers(&csv), next), (vec!["p0".to_string(), "p1".to_string()], Some(2)));
+        let (csv, next) = optimizer.export_usage_csv(3600, 2, 10, 0);
+        assert_eq!((providers(&csv), next), (vec!["p2".to_string()], Some(3)));
+    }
+
+    #[test]
+    fn never_pinged_provider_is_fresh_until_stale_after_registration() {
+        let provider = ServiceProvider::for_test("p", &["ethereum"], 100);
+        let registry = registry_with(std::slice::from_ref(&provider));
+        let settings = OptimizationSettings { stale_data_seconds: 60, ..OptimizationSettings::default() };
+        let optimizer = CostOptimizer::new(settings);
+
+        assert_eq!(optimizer.penalties(&registry, &provider, "ethereum", 1_000), 0);
+        crate::clock::advance_seconds(61);
+        assert_eq!(optimizer.penalties(&registry, &provider, "ethereum", 1_000), 50_000);
+    }
+
+    #[test]
+    fn pinned_recipient_overrides_scoring() {
+        let registry = registry_with(&[
+            ServiceProvider::for_test("pinned", &["ethereum"], 900),
+            ServiceProvider::for_test("cheap", &["ethereum", "polygon"], 100),
+        ]);
+        let optimizer = pinned_optimizer("0xabc", "pinned");
+        let route = |recipient: &str, chain: &str| optimizer.optimize_payment_route(&registry, chain, 1_000, Some(recipient), None, &[]);
+
+        assert_eq!(route("0xabc", "ethereum").as_deref(), Some("pinned"));
+        assert_eq!(route("0xdef", "ethereum").as_deref(), Some("cheap"));
+        assert_eq!(route("0xabc", "polygon").as_deref(), Some("cheap"));
+    }
+
+    #[test]
+    fn chains_without_recent_cost_data_are_stale() {
+        let mut optimizer = CostOptimizer::new(OptimizationSettings::default());
+        optimizer.update_chain_costs("ethereum", 100, true);
+        clock::advance_seconds(120);
+        optimizer.update_chain_costs("polygon", 100, true);
+        optimizer.update_chain_costs("arbitrum", 100, true);
+
+        assert_eq!(optimizer.stale_chains(60), vec!["ethereum".to_string()]);
+        assert!(optimizer.stale_chains(600).is_empty());
+        clock::advance_seconds(61);
+        assert_eq!(optimizer.stale_chains(60), vec!["arbitrum".to_string(), "ethereum".to_string(), "polygon".to_string()]);
+    }
+
+    #[test]
+    fn cost_efficiency_follows_configured_scale() {
+        let efficiency = |scale: f64| {
+            let mut optimizer = CostOptimizer::new(OptimizationSettings { cost_efficiency_scale: scale, ..Default::default() });
+            optimizer.record_usage("ethereum", "p", 500, true, 1.0, false);
+            optimizer.record_usage("ethereum", "p", 500, true, 1.0, false);
+            optimizer.get_usage_metrics(3600)
+        };
+
+        let per_million = efficiency(1_000_000.0);
+        assert!((per_million.cost_efficiency - 2_000.0).abs() < 1e-9);
+        assert_eq!(per_million.units.cost_efficiency_unit, "successful payments per 1000000 wei");
+        let per_thousand = efficiency(1_000.0);
+        assert!((per_thousand.cost_efficiency - 2.0).abs() < 1e-9);
+        assert_eq!(per_thousand.units.cost_efficiency_scale, 1_000.0);
+    }
+
+    #[test]
+    fn unresponsive_pin_falls_back_to_routing() {
+        let mut registry = registry_with(&[
+            ServiceProvider::for_test("pinned", &["ethereum"], 100),
+            ServiceProvider::for_test("other", &["ethereum"], 500),
+        ]);
+        let optimizer = pinned_optimizer("0xabc", "pinned");
+        registry.record_routed_activity("pinned");
+        assert_eq!(optimizer.optimize_payment_route(&registry, "ethereum", 1_000, Some("0xabc"), None, &[]).as_deref(), Some("pinned"));
+
+        clock::advance_seconds(optimizer.settings().unresponsive_after_seconds + 1);
+        assert_eq!(optimizer.optimize_payment_route(&registry, "ethereum", 1_000, Some("0xabc"), None, &[]).as_deref(), Some("other"));
+    }
+
+    #[test]
+    fn exhausted_pin_falls_back_to_routing() {
+        let mut pinned = ServiceProvider::for_test("pinned", &["ethereum"], 100);
+        pinned.quota = Some(Quota { total: 1, window_seconds: 60 });
+        let mut registry = registry_with(&[pinned, ServiceProvider::for_test("other", &["ethereum"], 500)]);
+        let optimizer = pinned_optimizer("0xabc", "pinned");
+        assert_eq!(optimizer.optimize_payment_route(&registry, "ethereum", 1_000, Some("0xabc"), None, &[]).as_deref(), Some("pinned"));
+
+        registry.consume_quota("pinned");
+        assert_eq!(optimizer.optimize_payment_route(&registry, "ethereum", 1_000, Some("0xabc"), None, &[]).as_deref(), Some("other"));
+    }
+
+    #[test]
+    fn provider_counters_outlive_history_eviction() {
+        let mut optimizer = CostOptimizer::new(OptimizationSettings::default());
+        for _ in 0..1_001 {
+            optimizer.record_usage("ethereum", "p", 100, true, 1.0, false);
+        }
+        optimizer.record_usage("ethereum", "p", 100, false, 1.0, false);
+
+        assert_eq!(optimizer.usage_history.len(), 1_000);
+        assert_eq!(optimizer.get_provider_counters("p"), (1_001, 1));
+        assert_eq!(optimizer.get_provider_counters("unknown"), (0, 0));
+    }
+
+    #[test]
+    fn maintenance_usage_leaves_standing_untouched() {
+        let mut optimizer = CostOptimizer::new(OptimizationSettings::default());
+        optimizer.record_usage("ethereum", "p", 100, true, 1.0, false);
+        optimizer.record_usage("ethereum", "p", 100, false, 1.0, true);
+        optimizer.record_usage("ethereum", "p", 100, true, 1.0, true);
+        assert_eq!(optimizer.get_provider_counters("p"), (1, 0));
+        assert!(!optimizer.last_failure.contains_key("p"));
+        assert_eq!(optimizer.usage_history.len(), 3);
+    }
+
+    #[test]
+    fn preferred_chain_mode_controls_rerouting() {
+        let registry = registry_with(&[
+            ServiceProvider::for_test("eth", &["Ethereum"], 500),
+            ServiceProvider::for_test("poly", &["Polygon"], 100),
+        ]);
+        let plan = |mode: PreferredChainMode, chain: &str| {
+            let optimizer = CostOptimizer::new(OptimizationSettings {
+                preferred_chains: vec!["Polygon".to_string()],
+                preferred_chain_mode: mode,
+                ..Default::default()
+            });
+            optimizer.plan_payment_route(&registry, chain, 1_000, None, None, &[], &|_| true)
+        };
+
+        let off = plan(PreferredChainMode::Off, "Ethereum");
+        assert_eq!((off.chain.as_str(), off.suggested_chain.as_deref(), off.rerouted), ("Ethereum", None, false));
+        let suggest = plan(PreferredChainMode::Suggest, "Ethereum");
+        assert_eq!((suggest.chain.as_str(), suggest.suggested_chain.as_deref(), suggest.rerouted), ("Ethereum", Some("Polygon"), false));
+        assert_eq!(suggest.provider_id.as_deref(), Some("eth"));
+        let enforce = plan(PreferredChainMode::Enforce, "Ethereum");
+        assert_eq!((enforce.chain.as_str(), enforce.provider_id.as_deref(), enforce.rerouted), ("Polygon", Some("poly"), true));
+        let preferred = plan(PreferredChainMode::Enforce, "Polygon");
+        assert_eq!((preferred.chain.as_str(), preferred.suggested_chain.as_deref()), ("Polygon", None));
+    }
+
+    #[test]
+    fn enforced_reroute_skips_chains_the_payment_cannot_use() {
+        let registry = registry_with(&[
+            ServiceProvider::for_test("eth", &["Ethereum"], 500),
+            ServiceProvider::for_test("sol", &["Solana"], 100),
+            ServiceProvider::for_test("poly", &["Polygon"], 100),
+        ]);
+        let optimizer = CostOptimizer::new(OptimizationSettings {
+            preferred_chain_mode: PreferredChainMode::Enforce,
+            chain_preferences: vec![
+                ChainPreference { name: "Solana".to_string(), weight: 2.0, is_fallback: false },
+                ChainPreference { name: "Polygon".to_string(), weight: 1.0, is_fallback: false },
+            ],
+            ..Default::default()
+        });
+        let recipient = "0x00000000000000000000000000000000000000aa";
+        let evm_only = |chain: &str| crate::chains::validate_recipient(chain, recipient).is_ok();
+
+        let plan = optimizer.plan_payment_route(&registry, "Ethereum", 1_000, Some(recipient), None, &[], &|_| true);
+        assert_eq!(plan.chain, "Solana");
+
+        let plan = optimizer.plan_payment_route(&registry, "Ethereum", 1_000, Some(recipient), None, &[], &evm_only);
+        assert_eq!((plan.chain.as_str(), plan.provider_id.as_deref()), ("Polygon", Some("poly")));
+
+        let plan = optimizer.plan_payment_route(&registry, "Ethereum", 1_000, Some(recipient), None, &[], &|chain| chain == "Ethereum");
+        assert_eq!((plan.chain.as_str(), plan.rerouted), ("Ethereum", false));
+    }
+
+    #[test]
+    fn running_chain_averages_match_brute_force_mean() {
+        for seed in 0..20 {
+            let mut rng = Prng::new(seed);
+            let mut optimizer = CostOptimizer::new(OptimizationSettings {
+                success_rate_prior_weight: 0.0,
+                ..Default::default()
+            });
+            let samples: Vec<(u64, bool)> = (0..1 + rng.next_u64() % 200)
+                .map(|_| (rng.next_u64() % 1_000_000, rng.next_f64() < 0.7))
+                .collect();
+            for &(cost, success) in &samples {
+                optimizer.update_chain_costs("ethereum", cost, success);
+            }
+
+            let n = samples.len() as f64;
+            let mean_cost = samples.iter().map(|&(cost, _)| cost as f64).sum::<f64>() / n;
+            let mean_success = samples.iter().filter(|&&(_, success)| success).count() as f64 / n;
+            let data = &optimizer.chain_costs["ethereum"];
+            assert_eq!(data.volume, samples.len() as u64);
+            assert!((data.average_cost - mean_cost).abs() < 1e-6, "seed {}", seed);
+            assert!((data.success_rate - mean_success).abs() < 1e-12, "seed {}", seed);
+        }
+    }
+
+    #[test]
+    fn cold_start_success_rate_is_smoothed_toward_prior() {
+        let mut optimizer = CostOptimizer::new(OptimizationSettings::default());
+        optimizer.update_chain_costs("ethereum", 100, true);
+        assert!((optimizer.chain_costs["ethereum"].success_rate - 2.0 / 3.0).abs() < 1e-12);
+
+        let mut optimizer = CostOptimizer::new(OptimizationSettings {
+            success_rate_prior: 0.9,
+            success_rate_prior_weight: 10.0,
+            ..Default::default()
+        });
+        optimizer.update_chain_costs("ethereum", 100, false);
+        assert!((optimizer.chain_costs["ethereum"].success_rate - 9.0 / 11.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn warming_up_providers_only_get_their_traffic_share() {
+        let mut registry = registry_with(&[ServiceProvider::for_test("seasoned", &["ethereum"], 500)]);
+        clock::advance_seconds(3_600);
+        registry.register_provider(ServiceProvider::for_test("fresh", &["ethereum"], 100), None).unwrap();
+        let optimizer_with = |fraction: f64| CostOptimizer::new(OptimizationSettings {
+            warmup_period_seconds: 600,
+            warmup_traffic_fraction: fraction,
+            stale_data_seconds: 0,
+            ..Default::default()
+        });
+        let route = |optimizer: &CostOptimizer| optimizer.optimize_payment_route(&registry, "ethereum", 1_000, None, None, &[]);
+
+        assert_eq!(route(&optimizer_with(0.0)).as_deref(), Some("seasoned"));
+        assert_eq!(route(&optimizer_with(1.0)).as_deref(), Some("fresh"));
+        let only_fresh = ["seasoned".to_string()];
+        assert_eq!(optimizer_with(0.0).optimize_payment_route(&registry, "ethereum", 1_000, None, None, &only_fresh).as_deref(), Some("fresh"));
+
+        clock::advance_seconds(601);
+        assert_eq!(route(&optimizer_with(0.0)).as_deref(), Some("fresh"));
+    }
+
+    #[test]
+    fn metrics_by_chain_split_the_window_per_chain() {
+        let mut optimizer = CostOptimizer::new(OptimizationSettings::default());
+        optimizer.record_usage("solana", "sol", 10, true, 0.1, false);
+        clock::advance_seconds(7_200);
+        optimizer.record_usage("solana", "sol", 10, false, 0.1, false);
+        optimizer.record_usage("ethereum", "eth", 100, true, 0.1, false);
+        optimizer.record_usage("ethereum", "eth", 100, true, 0.1, false);
+
+        let metrics = optimizer.get_metrics_by_chain(3_600);
+        let summary: Vec<(&str, u64, u64)> = metrics
+            .iter()
+            .map(|(chain, m)| (chain.as_str(), m.total_requests, m.successful_payments))
+            .collect();
+        assert_eq!(summary, vec![("ethereum", 2, 2), ("solana", 1, 0)]);
+    }
+
+    #[test]
+    fn tiered_pricing_picks_the_highest_reached_tier() {
+        let mut provider = ServiceProvider::for_test("p", &["ethereum"], 100);
+        provider.cost_tiers = Some(vec![
+            CostTier { min_amount: 10_000, cost_per_request: 40 },
+            CostTier { min_amount: 1_000, cost_per_request: 70 },
+        ]);
+        let optimizer = CostOptimizer::new(OptimizationSettings::default());
+
+        assert_eq!(optimizer.get_effective_cost(&provider, "ethereum", 999), Some(100));
+        assert_eq!(optimizer.get_effective_cost(&provider, "ethereum", 5_000), Some(70));
+        assert_eq!(optimizer.get_effective_cost(&provider, "ethereum", 10_000), Some(40));
+        assert_eq!(optimizer.get_effective_cost(&provider, "solana", 10_000), None);
+    }
+
+    #[test]
+    fn default_window_metrics_are_served_from_cache_until_ttl() {
+        let mut optimizer = CostOptimizer::new(OptimizationSettings::default());
+        optimizer.record_usage("ethereum", "p", 100, true, 0.1, false);
+        clock::advance_seconds(3_590);
+        optimizer.refresh_metrics_cache();
+
+        clock::advance_seconds(20);
+        assert_eq!(optimizer.get_usage_metrics(3_600).total_requests, 1);
+        assert_eq!(optimizer.get_usage_metrics(3_599).total_requests, 0);
+
+        clock::advance_seconds(60);
+        assert_eq!(optimizer.get_usage_metrics(3_600).total_requests, 0);
+
+        optimizer.refresh_metrics_cache();
+        optimizer.record_usage("ethereum", "p", 100, true, 0.1, false);
+        assert_eq!(optimizer.get_usage_metrics(3_600).total_requests, 1);
+    }
+
+    #[test]
+    fn learning_rate_switches_chain_costs_to_an_ema() {
+        let mut optimizer = CostOptimizer::new(OptimizationSettings::default());
+        for alpha in [0.0, 1.5, f64::NAN] {
+            let settings = OptimizationSettings { chain_cost_learning_rate: Some(alpha), ..Default::default() };
+            assert_eq!(optimizer.update_settings(settings), Err("chain_cost_learning_rate must be in (0, 1]".to_string()));
+        }
+        optimizer.update_settings(OptimizationSettings { chain_cost_learning_rate: Some(0.5), ..Default::default() }).unwrap();
+
+        optimizer.update_chain_costs("ethereum", 100, true);
+        optimizer.update_chain_costs("ethereum", 200, false);
+
+        let data = &optimizer.chain_costs["ethereum"];
+        assert!((data.average_cost - 150.0).abs() < 1e-12);
+        assert!((data.success_rate - 0.375).abs() < 1e-12);
+    }
+
+    #[test]
+    fn huge_costs_saturate_instead_of_overflowing() {
+        let mut optimizer = CostOptimizer::new(OptimizationSettings::default());
+        optimizer.record_usage("ethereum", "p", u64::MAX, true, 0.1, false);
+        optimizer.record_usage("ethereum", "p", u64::MAX, true, 0.1, false);
+
+        let metrics = optimizer.get_usage_metrics(3_600);
+        assert_eq!(metrics.total_volume, u64::MAX);
+        let expected = 2.0 / (2.0 * u64::MAX as f64) * optimizer.settings().cost_efficiency_scale;
+        assert!((metrics.cost_efficiency - expected).abs() < 1e-24);
+        assert_eq!(optimizer.chain_costs["ethereum"].cost_sum, 2 * u64::MAX as u128);
+    }
+
+    #[test]
+    fn empty_preferences_are_rejected_and_rebalancing_reviews_recorded_chains() {
+        let settings = OptimizationSettings { preferred_chains: Vec::new(), ..Default::default() };
+        let mut optimizer = CostOptimizer::new(OptimizationSettings::default());
+        assert_eq!(
+            optimizer.update_settings(settings.clone()),
+            Err("preferred_chains or chain_preferences must not be empty".to_string())
+        );
+
+        let mut optimizer = CostOptimizer::new(settings);
+        optimizer.update_chain_costs("unlisted", 100, false);
+        optimizer.update_chain_costs("steady", 100, true);
+        let suggestions = optimizer.suggest_chain_rebalancing();
+        let from: Vec<&str> = suggestions.iter().map(|s| s.from_chain.as_str()).collect();
+        assert!(from.contains(&"unlisted"));
+    }
+
+    #[test]
+    fn auto_rebalancing_moves_struggling_chain_behind_healthier_one() {
+        let mut optimizer = CostOptimizer::new(OptimizationSettings {
+            preferred_chains: vec!["ethereum".to_string(), "polygon".to_string()],
+            ..Default::default()
+        });
+        for _ in 0..5 {
+            optimizer.update_chain_costs("ethereum", 100, false);
+            optimizer.update_chain_costs("polygon", 100, true);
+        }
+        assert!(optimizer.apply_rebalancing().is_empty());
+
+        let settings = OptimizationSettings { auto_optimization_enabled: true, ..optimizer.settings().clone() };
+        optimizer.update_settings(settings).unwrap();
+        let applied = optimizer.apply_rebalancing();
+        assert_eq!(applied.len(), 1);
+        assert_eq!((applied[0].from_chain.as_str(), applied[0].to_chain.as_str()), ("ethereum", "polygon"));
+        assert_eq!(optimizer.settings().preferred_chains, vec!["polygon".to_string(), "ethereum".to_string()]);
+
+        assert!(optimizer.apply_rebalancing().is_empty());
+    }
+
+    #[test]
+    fn efficiency_ranking_weighs_smoothed_success_against_cost() {
+        let registry = registry_with(&[
+            ServiceProvider::for_test("proven", &["ethereum"], 100),
+            ServiceProvider::for_test("untried", &["ethereum"], 50),
+            ServiceProvider::for_test("failing", &["ethereum"], 100),
+        ]);
+        let mut optimizer = CostOptimizer::new(OptimizationSettings::default());
+        for _ in 0..8 {
+            optimizer.record_usage("ethereum", "proven", 100, true, 0.1, false);
+            optimizer.record_usage("ethereum", "failing", 100, false, 0.1, false);
+        }
+
+        let ranking = optimizer.provider_efficiency_ranking(&registry);
+        let expected = [("untried", 10_000.0), ("proven", 9_000.0), ("failing", 1_000.0)];
+        assert_eq!(ranking.len(), expected.len());
+        for ((id, efficiency), (expected_id, expected_efficiency)) in ranking.iter().zip(expected) {
+            assert_eq!(id, expected_id);
+            assert!((efficiency - expected_efficiency).abs() < 1e-6, "{}", id);
+        }
+    }
+
+    #[test]
+    fn low_sample_reliability_is_blended_toward_the_prior() {
+        let provider = ServiceProvider { reliability_score: 1.0, ..ServiceProvider::for_test("p", &["ethereum"], 100) };
+        let mut optimizer = CostOptimizer::new(OptimizationSettings { min_samples_for_full_trust: 10, ..Default::default() });
+        assert!((optimizer.trusted_reliability(&provider) - 0.5).abs() < 1e-12);
+
+        for _ in 0..5 {
+            optimizer.record_usage("ethereum", "p", 100, true, 0.1, false);
+        }
+        assert!((optimizer.trusted_reliability(&provider) - 0.75).abs() < 1e-12);
+
+        for _ in 0..10 {
+            optimizer.record_usage("ethereum", "p", 100, true, 0.1, false);
+        }
+        assert!((optimizer.trusted_reliability(&provider) - 1.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn forming_batches_discount_the_fixed_share_of_cost() {
+        let registry = registry_with(&[
+            ServiceProvider::for_test("batching", &["ethereum"], 100),
+            ServiceProvider::for_test("single", &["ethereum"], 90),
+        ]);
+        let mut optimizer = CostOptimizer::new(OptimizationSettings { batch_amortization_fraction: 0.5, ..Default::default() });
+        let route = |optimizer: &CostOptimizer| optimizer.optimize_payment_route(&registry, "ethereum", 1_000, None, None, &[]);
+        optimizer.begin_batch_round(4);
+        assert_eq!(optimizer.amortized_cost("batching", "ethereum", 100), 100);
+        assert_eq!(route(&optimizer).as_deref(), Some("single"));
+
+        optimizer.note_batch_member("batching", "ethereum");
+        assert_eq!(optimizer.amortized_cost("batching", "ethereum", 100), 75);
+        optimizer.note_batch_member("batching", "ethereum");
+        assert_eq!(route(&optimizer).as_deref(), Some("batching"));
+        for _ in 0..2 {
+            optimizer.note_batch_member("batching", "ethereum");
+        }
+        assert_eq!(optimizer.amortized_cost("batching", "ethereum", 100), 100);
+
+        optimizer.note_batch_member("batching", "ethereum");
+        optimizer.end_batch_round();
+        assert_eq!(optimizer.amortized_cost("batching", "ethereum", 100), 100);
+    }
+
+    #[test]
+    fn normalized_scores_keep_one_dimension_from_dominating() {
+        let registry = registry_with(&[
+            ServiceProvider { reliability_score: 0.99, ..ServiceProvider::for_test("reliable", &["ethereum"], 100) },
+            ServiceProvider { reliability_score: 0.91, ..ServiceProvider::for_test("cheap", &["ethereum"], 50) },
+        ]);
+        let route = |normalize_scores: bool| {
+            let settings = OptimizationSettings { normalize_scores, reliability_threshold: 0.0, ..Default::default() };
+            CostOptimizer::new(settings).optimize_payment_route(&registry, "ethereum", 1_000, None, None, &[])
+        };
+
+        assert_eq!(route(false).as_deref(), Some("reliable"));
+        assert_eq!(route(true).as_deref(), Some("cheap"));
+    }
+
+    #[test]
+    fn recent_failure_penalty_fades_over_the_window() {
+        let provider = ServiceProvider::for_test("p", &["ethereum"], 100);
+        let registry = registry_with(std::slice::from_ref(&provider));
+        let mut optimizer = CostOptimizer::new(OptimizationSettings {
+            recent_failure_window_seconds: 100,
+            stale_data_seconds: 0,
+            ..Default::default()
+        });
+        let penalty = |optimizer: &CostOptimizer| optimizer.penalties(&registry, &provider, "ethereum", 1_000);
+        optimizer.record_usage("ethereum", "p", 100, true, 0.1, false);
+        assert_eq!(penalty(&optimizer), 0);
+
+        optimizer.record_usage("ethereum", "p", 100, false, 0.1, false);
+        assert_eq!(penalty(&optimizer), 500_000);
+        clock::advance_seconds(50);
+        assert_eq!(penalty(&optimizer), 250_000);
+        clock::advance_seconds(50);
+        assert_eq!(penalty(&optimizer), 0);
+    }
+
+    #[test]
+    fn rebalancing_preview_moves_the_struggling_share() {
+        let mut optimizer = CostOptimizer::new(OptimizationSettings {
+            preferred_chains: vec!["ethereum".to_string()],
+            ..Default::default()
+        });
+        for _ in 0..3 {
+            optimizer.record_usage("ethereum", "eth", 100, false, 0.1, false);
+        }
+        optimizer.record_usage("polygon", "poly", 100, true, 0.1, false);
+
+        let preview = optimizer.preview_rebalancing();
+        assert_eq!(preview.current_distribution, vec![("ethereum".to_string(), 0.75), ("polygon".to_string(), 0.25)]);
+        assert_eq!(preview.projected_distribution, vec![("ethereum".to_string(), 0.0), ("polygon".to_string(), 1.0)]);
+        assert_eq!(preview.suggestions.len(), 1);
+        assert_eq!(preview.total_projected_savings, preview.suggestions[0].potential_savings);
+    }
+
+    #[test]
+    fn fallback_chains_are_used_only_when_no_primary_can_route() {
+        let preference = |name: &str, weight: f64, is_fallback: bool| ChainPreference { name: name.to_string(), weight, is_fallback };
+        let optimizer = CostOptimizer::new(OptimizationSettings {
+            chain_preferences: vec![preference("Base", 9.0, true), preference("Polygon", 2.0, false), preference("Arbitrum", 5.0, false)],
+            preferred_chain_mode: PreferredChainMode::Enforce,
+            ..Default::default()
+        });
+        let order: Vec<String> = optimizer.chain_preferences().into_iter().map(|p| p.name).collect();
+        assert_eq!(order, vec!["Arbitrum", "Polygon", "Base"]);
+
+        let mut registry = registry_with(&[
+            ServiceProvider::for_test("eth", &["Ethereum"], 100),
+            ServiceProvider::for_test("base", &["Base"], 500),
+        ]);
+        let plan = |registry: &ServiceRegistry, chain: &str| {
+            let plan = optimizer.plan_payment_route(registry, chain, 1_000, None, None, &[], &|_| true);
+            (plan.chain, plan.rerouted)
+        };
+        assert_eq!(plan(&registry, "Ethereum"), ("Base".to_string(), true));
+        assert_eq!(plan(&registry, "Base"), ("Base".to_string(), false));
+
+        registry.register_provider(ServiceProvider::for_test("poly", &["Polygon"], 500), None).unwrap();
+        assert_eq!(plan(&registry, "Ethereum"), ("Polygon".to_string(), true));
+        assert_eq!(plan(&registry, "Base"), ("Polygon".to_string(), true));
+    }
+
+    #[test]
+    fn capacity_penalty_scales_with_the_share_of_capacity_used() {
+        let small = ServiceProvider { capacity: Some(10_000), ..ServiceProvider::for_test("small", &["ethereum"], 100) };
+        let unbounded = ServiceProvider::for_test("unbounded", &["ethereum"], 100);
+        let registry = registry_with(&[small.clone(), unbounded.clone()]);
+        let mut optimizer = CostOptimizer::new(OptimizationSettings { capacity_weight: 0.5, stale_data_seconds: 0, ..Default::default() });
+
+        assert_eq!(optimizer.penalties(&registry, &small, "ethereum", 1_000), 50_000);
+        assert_eq!(optimizer.penalties(&registry, &small, "ethereum", 20_000), 500_000);
+        assert_eq!(optimizer.penalties(&registry, &unbounded, "ethereum", 20_000), 0);
+
+        optimizer.begin_batch_round(4);
+        optimizer.note_batch_member("small", "ethereum");
+        optimizer.note_batch_member("small", "ethereum");
+        assert_eq!(optimizer.penalties(&registry, &small, "ethereum", 1_000), 150_000);
+        assert_eq!(optimizer.optimize_payment_route(&registry, "ethereum", 5_000, None, None, &[]).as_deref(), Some("unbounded"));
+    }
+
+    #[test]
+    fn bridging_fee_can_outweigh_preferred_chain_savings() {
+        let registry = registry_with(&[
+            ServiceProvider::for_test("eth", &["Ethereum"], 500),
+            ServiceProvider::for_test("poly", &["Polygon"], 100),
+        ]);
+        let plan = |bridge: u64| {
+            let mut settings = OptimizationSettings {
+                preferred_chains: vec!["Polygon".to_string()],
+                preferred_chain_mode: PreferredChainMode::Enforce,
+                ..Default::default()
+            };
+            settings.cross_chain_cost.insert(("Ethereum".to_string(), "Polygon".to_string()), bridge);
+            let plan = CostOptimizer::new(settings).plan_payment_route(&registry, "Ethereum", 1_000, None, None, &[], &|_| true);
+            (plan.chain, plan.provider_id)
+        };
+
+        assert_eq!(plan(400), ("Polygon".to_string(), Some("poly".to_string())));
+        assert_eq!(plan(401), ("Ethereum".to_string(), Some("eth".to_string())));
+    }
+
+    #[test]
+    fn on_time_rate_counts_successes_within_chain_targets() {
+        let mut settings = OptimizationSettings::default();
+        settings.target_settlement_seconds.insert("ethereum".to_string(), 2.0);
+        let mut optimizer = CostOptimizer::new(settings);
+        for (chain, success, response_time) in [
+            ("ethereum", true, 1.5),
+            ("ethereum", true, 2.0),
+            ("ethereum", true, 3.0),
+            ("ethereum", false, 0.5),
+            ("solana", true, 9.0),
+        ] {
+            optimizer.record_usage(chain, "p", 100, success, response_time, false);
+        }
+
+        assert!((optimizer.get_usage_metrics(3_600).on_time_rate - 2.0 / 3.0).abs() < 1e-12);
+        assert_eq!(CostOptimizer::new(OptimizationSettings::default()).get_usage_metrics(3_600).on_time_rate, 0.0);
+    }
+
+    #[test]
+    fn settings_profiles_round_trip_through_validation() {
+        let mut optimizer = CostOptimizer::new(OptimizationSettings::default());
+        assert_eq!(optimizer.save_settings_profile(""), Err("Profile name must not be empty".to_string()));
+        optimizer.save_settings_profile("normal").unwrap();
+
+        let thrifty = OptimizationSettings { max_cost_per_transaction: 500, ..optimizer.settings().clone() };
+        optimizer.update_settings(thrifty).unwrap();
+        optimizer.save_settings_profile("thrifty").unwrap();
+        assert_eq!(optimizer.list_settings_profiles(), vec!["normal", "thrifty"]);
+
+        optimizer.load_settings_profile("normal").unwrap();
+        assert_eq!(optimizer.settings().max_cost_per_transaction, OptimizationSettings::default().max_cost_per_transaction);
+        optimizer.load_settings_profile("thrifty").unwrap();
+        assert_eq!(optimizer.settings().max_cost_per_transaction, 500);
+        assert_eq!(optimizer.load_settings_profile("missing"), Err("Settings profile not found".to_string()));
+        assert_eq!(optimizer.settings().max_cost_per_transaction, 500);
+    }
+
+    #[test]
+    fn fixed_point_scores_are_reproducible() {
+        let build = || {
+            let registry = registry_with(&[
+                ServiceProvider::for_test("a", &["ethereum"], 333),
+                ServiceProvider::for_test("b", &["ethereum"], 334),
+            ]);
+            let mut optimizer = CostOptimizer::new(OptimizationSettings::default());
+            for i in 0..50u64 {
+                optimizer.record_usage("ethereum", if i % 3 == 0 { "a" } else { "b" }, 300 + i % 7, i % 5 != 0, 0.1 * i as f64, false);
+            }
+            (registry, optimizer)
+        };
+
+        let (registry, optimizer) = build();
+        let provider = registry.get_provider("a").unwrap();
+        let first = optimizer.calculate_optimization_score(&registry, provider, "ethereum", 1_000);
+        for _ in 0..10 {
+            let (registry, optimizer) = build();
+            let provider = registry.get_provider("a").unwrap();
+            assert_eq!(optimizer.calculate_optimization_score(&registry, provider, "ethereum", 1_000), first);
+        }
+    }
+
+    #[test]
+    fn comparison_winner_matches_routing() {
+        let registry = registry_with(&[
+            ServiceProvider::for_test("b", &["ethereum"], 100),
+            ServiceProvider::for_test("a", &["ethereum"], 100),
+        ]);
+        let optimizer = CostOptimizer::new(OptimizationSettings::default());
+        let routed = optimizer.optimize_payment_route(&registry, "ethereum", 1_000, None, None, &[]);
+        assert_eq!(routed.as_deref(), Some("a"));
+        assert_eq!(optimizer.compare_providers(&registry, "b", "a", "ethereum", 1_000).unwrap().winner, routed);
+
+        let optimizer = CostOptimizer::new(OptimizationSettings { normalize_scores: true, ..Default::default() });
+        let routed = optimizer.optimize_payment_route(&registry, "ethereum", 1_000, None, None, &[]);
+        assert_eq!(optimizer.compare_providers(&registry, "b", "a", "ethereum", 1_000).unwrap().winner, routed);
+    }
+
+    #[test]
+    fn reset_chain_data_is_idempotent() {
+        let mut optimizer = CostOptimizer::new(OptimizationSettings::default());
+        optimizer.record_usage("Ethereum", "p", 100, true, 1.0, false);
+        optimizer.record_usage("Polygon", "p", 100, true, 1.0, false);
+
+        assert!(optimizer.reset_chain_data("Ethereum"));
+        assert!(!optimizer.reset_chain_data("Ethereum"));
+        assert!(!optimizer.chain_costs.contains_key("Ethereum"));
+        assert!(optimizer.usage_history.iter().all(|r| r.chain == "Polygon"));
+    }
+
+    #[test]
+    fn csv_pages_stop_at_byte_budget() {
+        let mut optimizer = CostOptimizer::new(OptimizationSettings::default());
+        for _ in 0..10 {
+            optimizer.record_usage("Ethereum", "p", 100, true, 1.0, false);
+        }
+
+        let (full, next) = optimizer.export_usage_csv(3600, 0, usize::MAX, usize::MAX);
+        assert_eq!((full.lines().count(), next), (11, None));
+
+        let row_bytes = full.lines().nth(1).unwrap().len() + 1;
+        let header_bytes = full.lines().next().unwrap().len() + 1;
+        let (page, next) = optimizer.export_usage_csv(3600, 0, usize::MAX, header_bytes + 3 * row_bytes);
+        assert_eq!((page.lines().count(), next), (4, Some(3)));
+
+        let (page, next) = optimizer.export_usage_csv(3600, 9, usize::MAX, 0);
+        assert_eq!((page.lines().count(), next), (2, None));
+    }
+}
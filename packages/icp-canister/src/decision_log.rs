@@ -0,0 +1,58 @@
+use crate::cost_optimizer::ProviderScore;
+use crate::clock::time;
+use std::collections::{HashMap, VecDeque};
+
+const MAX_DECISIONS: usize = 1000;
+
+/// Audit record of how a payment was routed: every provider considered on the
+/// routed chain with its score, and the provider that was selected.
+#[derive(Clone, Debug, candid::CandidType, serde::Serialize, serde::Deserialize)]
+pub struct RoutingDecision {
+    pub payment_id: String,
+    pub timestamp: u64,
+    pub chain: String,
+    pub candidates: Vec<ProviderScore>,
+    pub selected_provider: Option<String>,
+}
+
+pub struct DecisionLog {
+    decisions: HashMap<String, RoutingDecision>,
+    // Payment ids oldest first, for eviction
+    order: VecDeque<String>,
+}
+
+impl DecisionLog {
+    pub fn new() -> Self {
+        Self {
+            decisions: HashMap::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    /// Records the latest routing decision for a payment; a retried payment
+    /// replaces its earlier decision.
+    pub fn record(&mut self, payment_id: &str, chain: &str, candidates: Vec<ProviderScore>, selected_provider: Option<String>) {
+        let decision = RoutingDecision {
+            payment_id: payment_id.to_string(),
+            timestamp: time(),
+            chain: chain.to_string(),
+            candidates,
+            selected_provider,
+        };
+        if self.decisions.insert(payment_id.to_string(), decision).is_some() {
+            self.order.retain(|id| id != payment_id);
+        }
+        self.order.push_back(payment_id.to_string());
+
+        // Keep only the most recent decisions
+        while self.order.len() > MAX_DECISIONS {
+            if let Some(oldest) = self.order.pop_front() {
+                self.decisions.remove(&oldest);
+            }
+        }
+    }
+
+    pub fn get(&self, payment_id: &str) -> Option<RoutingDecision> {
+        self.decisions.get(payment_id).cloned()
+    }
+}
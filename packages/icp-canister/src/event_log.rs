@@ -0,0 +1,39 @@
+use crate::types::{Event, EventKind};
+use crate::clock::time;
+use std::collections::VecDeque;
+
+const MAX_EVENTS: usize = 1000;
+
+pub struct EventLog {
+    events: VecDeque<Event>,
+    next_id: u64,
+}
+
+impl EventLog {
+    pub fn new() -> Self {
+        Self {
+            events: VecDeque::new(),
+            next_id: 0,
+        }
+    }
+
+    pub fn emit(&mut self, kind: EventKind) {
+        self.events.push_back(Event {
+            id: self.next_id,
+            timestamp: time(),
+            kind,
+        });
+        self.next_id += 1;
+
+        // Keep only the most recent events
+        if self.events.len() > MAX_EVENTS {
+            self.events.pop_front();
+        }
+    }
+
+    /// Most recent events, newest last.
+    pub fn recent(&self, limit: usize) -> Vec<Event> {
+        let skip = self.events.len().saturating_sub(limit);
+        self.events.iter().skip(skip).cloned().collect()
+    }
+}
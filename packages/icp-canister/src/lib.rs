@@ -1,22 +1,44 @@
 mod types;
+mod chains;
 mod service_registry;
 mod payment_processor;
 mod cost_optimizer;
+mod event_log;
+mod decision_log;
+mod processing_trace;
+mod registration;
+mod memory_layout;
+mod rng;
+mod clock;
+mod runtime;
 
 use candid::{candid_method, Principal};
-use ic_cdk::api::time;
+use crate::clock::time;
 use ic_cdk::{init, update, query};
 use ic_stable_structures::memory_manager::{MemoryManager, VirtualMemory};
 use ic_stable_structures::DefaultMemoryImpl;
 use std::cell::RefCell;
+use std::collections::HashMap;
 
 use types::*;
 use service_registry::ServiceRegistry;
 use payment_processor::PaymentProcessor;
-use cost_optimizer::{CostOptimizer, RebalancingSuggestion};
+use cost_optimizer::{ChainStatus, CostOptimizer, ProviderComparison, RebalancingPreview, RebalancingSuggestion, RoutePlan};
+use event_log::EventLog;
+use decision_log::{DecisionLog, RoutingDecision};
+use processing_trace::{ProcessingAttempt, ProcessingTrace};
+use registration::RegistrationSettings;
 
 type Memory = VirtualMemory<DefaultMemoryImpl>;
 
+// Readiness thresholds
+const LOW_CYCLES_THRESHOLD: u128 = 1_000_000_000_000; // 1T cycles
+const MAX_PENDING_BACKLOG: u64 = 1000;
+// Stay well under the 2 MiB reply limit for query responses
+const MAX_EXPORT_BYTES: usize = 1_500_000;
+const STALE_PROVIDER_NANOS: u64 = 3600 * 1_000_000_000; // 1 hour without a ping
+const AUTO_PROCESSING_INTERVAL_SECONDS: u64 = 60;
+
 thread_local! {
     static MEMORY_MANAGER: RefCell<MemoryManager<DefaultMemoryImpl>> = 
         RefCell::new(MemoryManager::init(DefaultMemoryImpl::default()));
@@ -24,14 +46,29 @@ thread_local! {
     static SERVICE_REGISTRY: RefCell<ServiceRegistry> = RefCell::new(ServiceRegistry::new());
     static PAYMENT_PROCESSOR: RefCell<PaymentProcessor> = RefCell::new(PaymentProcessor::new());
     static COST_OPTIMIZER: RefCell<CostOptimizer> = RefCell::new(CostOptimizer::new(OptimizationSettings::default()));
+    static EVENT_LOG: RefCell<EventLog> = RefCell::new(EventLog::new());
+    static DECISION_LOG: RefCell<DecisionLog> = RefCell::new(DecisionLog::new());
+    static PROCESSING_TRACE: RefCell<ProcessingTrace> = RefCell::new(ProcessingTrace::new());
     
     static AUTHORIZED_PRINCIPALS: RefCell<Vec<Principal>> = RefCell::new(Vec::new());
+    // Expiry (nanoseconds) for temporary grants; permanent grants have no entry
+    static PRINCIPAL_EXPIRY: RefCell<HashMap<Principal, u64>> = RefCell::new(HashMap::new());
+    // Reason given to emergency_stop while the service is halted
+    static EMERGENCY_STOP: RefCell<Option<String>> = const { RefCell::new(None) };
+    // When the auto-processing timer was set up or last ran (nanoseconds)
+    static LAST_TIMER_TICK: RefCell<u64> = const { RefCell::new(0) };
+    // Trusted keys and whether registration signatures are checked against them
+    static REGISTRATION: RefCell<RegistrationSettings> = const { RefCell::new(RegistrationSettings::new()) };
 }
 
 // Initialization
 #[init]
 fn init() {
-    let caller = ic_cdk::caller();
+    if let Err(e) = memory_layout::validate_layout() {
+        ic_cdk::trap(&e);
+    }
+
+    let caller = runtime::caller();
     AUTHORIZED_PRINCIPALS.with(|principals| {
         principals.borrow_mut().push(caller);
     });
@@ -42,7 +79,11 @@ fn init() {
 
 // Authorization guard
 fn is_authorized() -> Result<(), String> {
-    let caller = ic_cdk::caller();
+    let caller = runtime::caller();
+    if grant_expired(&caller, time()) {
+        return Err("Authorization expired".to_string());
+    }
+
     AUTHORIZED_PRINCIPALS.with(|principals| {
         if principals.borrow().contains(&caller) || caller == Principal::anonymous() {
             Ok(())
@@ -52,19 +93,111 @@ fn is_authorized() -> Result<(), String> {
     })
 }
 
+// Admin guard for actions that undo a safety measure; only canister controllers pass
+fn is_admin() -> Result<(), String> {
+    if runtime::is_controller(&runtime::caller()) {
+        Ok(())
+    } else {
+        Err("Only a controller can do this".to_string())
+    }
+}
+
+// Kill switch: while set, every mutating call except authorization management
+// and clear_emergency_stop is rejected, and the timer stops processing
+fn ensure_not_halted() -> Result<(), String> {
+    EMERGENCY_STOP.with(|stop| match stop.borrow().as_ref() {
+        Some(reason) => Err(format!("ServiceHalted: {}", reason)),
+        None => Ok(()),
+    })
+}
+
+#[update]
+#[candid_method(update)]
+fn emergency_stop(reason: String) -> Result<String, String> {
+    is_admin()?;
+
+    EMERGENCY_STOP.with(|stop| {
+        *stop.borrow_mut() = Some(reason.clone());
+    });
+    EVENT_LOG.with(|log| {
+        log.borrow_mut().emit(EventKind::ServiceHalted { reason });
+    });
+
+    Ok("Emergency stop engaged".to_string())
+}
+
+#[update]
+#[candid_method(update)]
+fn clear_emergency_stop() -> Result<String, String> {
+    is_admin()?;
+
+    EMERGENCY_STOP.with(|stop| {
+        *stop.borrow_mut() = None;
+    });
+    EVENT_LOG.with(|log| {
+        log.borrow_mut().emit(EventKind::ServiceResumed);
+    });
+
+    Ok("Emergency stop cleared".to_string())
+}
+
+#[query]
+#[candid_method(query)]
+fn get_emergency_stop() -> Option<String> {
+    EMERGENCY_STOP.with(|stop| stop.borrow().clone())
+}
+
 // Service Registry Methods
 #[update]
 #[candid_method(update)]
 fn register_service_provider(provider: ServiceProvider) -> Result<String, String> {
     is_authorized()?;
+    ensure_not_halted()?;
+    REGISTRATION.with(|settings| registration::verify_registration(&provider, &settings.borrow()))?;
     
+    let max_providers_per_chain = COST_OPTIMIZER.with(|optimizer| {
+        optimizer.borrow().settings().max_providers_per_chain
+    });
     SERVICE_REGISTRY.with(|registry| {
-        registry.borrow_mut().register_provider(provider)
+        registry.borrow_mut().register_provider(provider, max_providers_per_chain)
     })?;
+    requeue_blocked_payments_internal();
     
     Ok("Provider registered successfully".to_string())
 }
 
+#[update]
+#[candid_method(update)]
+fn replace_provider(old_id: String, new_provider: ServiceProvider) -> Result<String, String> {
+    is_authorized()?;
+    ensure_not_halted()?;
+    REGISTRATION.with(|settings| registration::verify_registration(&new_provider, &settings.borrow()))?;
+
+    let max_providers_per_chain = COST_OPTIMIZER.with(|optimizer| {
+        optimizer.borrow().settings().max_providers_per_chain
+    });
+    // The registry checks everything that can fail before changing anything, so
+    // the handover below only runs once the swap has happened
+    let new_id = SERVICE_REGISTRY.with(|registry| {
+        registry.borrow_mut().replace_provider(&old_id, new_provider, max_providers_per_chain)
+    })?;
+    PAYMENT_PROCESSOR.with(|processor| {
+        processor.borrow_mut().transfer_provider(&old_id, &new_id)
+    });
+    COST_OPTIMIZER.with(|optimizer| {
+        optimizer.borrow_mut().transfer_provider(&old_id, &new_id)
+    });
+    requeue_blocked_payments_internal();
+
+    Ok(new_id)
+}
+
+#[query]
+#[candid_method(query)]
+fn generate_provider_id(name: String, api_endpoint: String) -> String {
+    service_registry::generate_provider_id(&name, &api_endpoint)
+}
+
 #[query]
 #[candid_method(query)]
 fn get_service_provider(provider_id: String) -> Option<ServiceProvider> {
@@ -73,6 +206,16 @@ fn get_service_provider(provider_id: String) -> Option<ServiceProvider> {
     })
 }
 
+#[query]
+#[candid_method(query)]
+fn get_provider_detail(provider_id: String) -> Option<ProviderDetail> {
+    SERVICE_REGISTRY.with(|registry| {
+        COST_OPTIMIZER.with(|optimizer| {
+            registry.borrow().get_provider_detail(&provider_id, optimizer.borrow().settings())
+        })
+    })
+}
+
 #[query]
 #[candid_method(query)]
 fn list_service_providers() -> Vec<ServiceProvider> {
@@ -83,184 +226,1752 @@ fn list_service_providers() -> Vec<ServiceProvider> {
 
 #[update]
 #[candid_method(update)]
-fn deactivate_service_provider(provider_id: String) -> Result<String, String> {
+async fn sync_providers_from(canister: Principal) -> Result<u64, String> {
     is_authorized()?;
-    
+    ensure_not_halted()?;
+
+    let (providers,): (Vec<ServiceProvider>,) = ic_cdk::call(canister, "list_service_providers", ())
+        .await
+        .map_err(|(code, msg)| format!("Call to {} failed: {:?} {}", canister, code, msg))?;
+
+    import_synced_providers(providers)
+}
+
+#[update]
+#[candid_method(update)]
+fn import_provider_manifest(manifest_json: String, signature: Vec<u8>) -> Result<u64, String> {
+    is_authorized()?;
+    ensure_not_halted()?;
+    REGISTRATION.with(|settings| registration::verify_manifest(manifest_json.as_bytes(), &signature, &settings.borrow().trusted_keys))?;
+
+    let providers: Vec<ServiceProvider> = serde_json::from_str(&manifest_json)
+        .map_err(|e| format!("Malformed manifest: {}", e))?;
+    if providers.iter().any(|provider| provider.id.is_empty()) {
+        return Err("Malformed manifest: every provider needs an id".to_string());
+    }
+
+    // Entries the registry refuses (blocklisted, over the per-chain cap) are skipped
+    let max_providers_per_chain = COST_OPTIMIZER.with(|optimizer| {
+        optimizer.borrow().settings().max_providers_per_chain
+    });
+    let applied = SERVICE_REGISTRY.with(|registry| {
+        let mut registry = registry.borrow_mut();
+        providers
+            .into_iter()
+            .filter(|provider| registry.apply_manifest_provider(provider.clone(), max_providers_per_chain).is_ok())
+            .count() as u64
+    });
+    if applied > 0 {
+        requeue_blocked_payments_internal();
+    }
+
+    Ok(applied)
+}
+
+#[query]
+#[candid_method(query)]
+fn list_providers_min_reliability(threshold: f64, chain: Option<String>) -> Result<Vec<ServiceProvider>, String> {
+    if !(0.0..=1.0).contains(&threshold) {
+        return Err("Threshold must be between 0 and 1".to_string());
+    }
+
+    Ok(SERVICE_REGISTRY.with(|registry| {
+        registry.borrow()
+            .providers_min_reliability(threshold, chain.as_deref())
+            .into_iter()
+            .cloned()
+            .collect()
+    }))
+}
+
+#[update]
+#[candid_method(update)]
+fn update_provider_cost(provider_id: String, cost_per_request: u64) -> Result<String, String> {
+    is_authorized()?;
+    ensure_not_halted()?;
+
     SERVICE_REGISTRY.with(|registry| {
-        registry.borrow_mut().deactivate_provider(&provider_id)
+        registry.borrow_mut().update_provider_cost(&provider_id, cost_per_request)
     })?;
-    
-    Ok("Provider deactivated successfully".to_string())
+    // A cheaper provider may now fit under max_cost_per_transaction
+    requeue_blocked_payments_internal();
+
+    Ok("Provider cost updated successfully".to_string())
 }
 
-// Payment Processing Methods
 #[update]
 #[candid_method(update)]
-fn submit_payment(payment: PaymentRequest) -> Result<String, String> {
+fn add_provider_chain(provider_id: String, chain: String) -> Result<String, String> {
     is_authorized()?;
-    
-    PAYMENT_PROCESSOR.with(|processor| {
-        processor.borrow_mut().submit_payment(payment)
-    })
+    ensure_not_halted()?;
+    chains::get_known_chain(&chain).ok_or_else(|| format!("Unknown chain {}", chain))?;
+
+    let max_providers_per_chain = COST_OPTIMIZER.with(|optimizer| {
+        optimizer.borrow().settings().max_providers_per_chain
+    });
+    SERVICE_REGISTRY.with(|registry| {
+        registry.borrow_mut().add_provider_chain(&provider_id, &chain, max_providers_per_chain)
+    })?;
+    requeue_blocked_payments_internal();
+
+    Ok("Provider chain added successfully".to_string())
 }
 
 #[update]
 #[candid_method(update)]
-fn process_payment(payment_id: String) -> Result<String, String> {
+fn remove_provider_chain(provider_id: String, chain: String, force: bool) -> Result<String, String> {
     is_authorized()?;
-    
-    PAYMENT_PROCESSOR.with(|processor| {
-        processor.borrow_mut().process_payment(&payment_id)
+    ensure_not_halted()?;
+
+    SERVICE_REGISTRY.with(|registry| {
+        registry.borrow_mut().remove_provider_chain(&provider_id, &chain, force)
     })?;
-    
-    Ok("Payment processed successfully".to_string())
+
+    Ok("Provider chain removed successfully".to_string())
 }
 
 #[query]
 #[candid_method(query)]
-fn get_payment_status(payment_id: String) -> Option<PaymentStatus> {
-    PAYMENT_PROCESSOR.with(|processor| {
-        processor.borrow().get_payment_status(&payment_id)
+fn get_provider_cost_history(provider_id: String) -> Vec<(u64, u64)> {
+    SERVICE_REGISTRY.with(|registry| {
+        registry.borrow().cost_history(&provider_id)
     })
 }
 
-#[query]
-#[candid_method(query)]
-fn list_pending_payments() -> Vec<PaymentRequest> {
-    PAYMENT_PROCESSOR.with(|processor| {
-        processor.borrow().list_pending_payments().into_iter().cloned().collect()
-    })
+#[update]
+#[candid_method(update)]
+fn activate_service_provider(provider_id: String) -> Result<String, String> {
+    is_authorized()?;
+    ensure_not_halted()?;
+    
+    SERVICE_REGISTRY.with(|registry| {
+        registry.borrow_mut().activate_provider(&provider_id)
+    })?;
+    requeue_blocked_payments_internal();
+    
+    Ok("Provider activated successfully".to_string())
 }
 
 #[update]
 #[candid_method(update)]
-fn cancel_payment(payment_id: String) -> Result<String, String> {
+fn deactivate_service_provider(provider_id: String) -> Result<String, String> {
     is_authorized()?;
+    ensure_not_halted()?;
     
-    PAYMENT_PROCESSOR.with(|processor| {
-        processor.borrow_mut().cancel_payment(&payment_id)
+    SERVICE_REGISTRY.with(|registry| {
+        registry.borrow_mut().deactivate_provider(&provider_id)
     })?;
     
-    Ok("Payment cancelled successfully".to_string())
+    Ok("Provider deactivated successfully".to_string())
+}
+
+#[update]
+#[candid_method(update)]
+fn begin_provider_maintenance(provider_id: String) -> Result<String, String> {
+    is_authorized()?;
+    ensure_not_halted()?;
+
+    SERVICE_REGISTRY.with(|registry| {
+        registry.borrow_mut().begin_maintenance(&provider_id)
+    })?;
+
+    Ok("Provider maintenance started".to_string())
+}
+
+#[update]
+#[candid_method(update)]
+fn end_provider_maintenance(provider_id: String) -> Result<String, String> {
+    is_authorized()?;
+    ensure_not_halted()?;
+
+    SERVICE_REGISTRY.with(|registry| {
+        registry.borrow_mut().end_maintenance(&provider_id)
+    })?;
+    requeue_blocked_payments_internal();
+
+    Ok("Provider maintenance ended".to_string())
+}
+
+#[update]
+#[candid_method(update)]
+fn blocklist_provider(provider_id: String) -> Result<String, String> {
+    is_admin()?;
+    ensure_not_halted()?;
+    
+    SERVICE_REGISTRY.with(|registry| {
+        registry.borrow_mut().blocklist_provider(&provider_id)
+    });
+    
+    Ok("Provider blocklisted successfully".to_string())
+}
+
+#[update]
+#[candid_method(update)]
+fn unblocklist_provider(provider_id: String) -> Result<String, String> {
+    is_admin()?;
+    ensure_not_halted()?;
+    
+    SERVICE_REGISTRY.with(|registry| {
+        registry.borrow_mut().unblocklist_provider(&provider_id)
+    })?;
+    requeue_blocked_payments_internal();
+    
+    Ok("Provider removed from blocklist successfully".to_string())
 }
 
-// Cost Optimization Methods
 #[query]
 #[candid_method(query)]
-fn optimize_payment_route(chain: String, amount: u64) -> Option<String> {
+fn list_blocklisted_providers() -> Vec<String> {
     SERVICE_REGISTRY.with(|registry| {
-        COST_OPTIMIZER.with(|optimizer| {
-            optimizer.borrow().optimize_payment_route(&registry.borrow(), &chain, amount)
-        })
+        registry.borrow().list_blocklist()
     })
 }
 
 #[query]
 #[candid_method(query)]
-fn get_rebalancing_suggestions() -> Vec<RebalancingSuggestion> {
-    COST_OPTIMIZER.with(|optimizer| {
-        optimizer.borrow().suggest_chain_rebalancing()
+fn get_chain_provider_counts() -> Vec<(String, u64)> {
+    let known: Vec<String> = chains::known_chains().into_iter().map(|c| c.name).collect();
+    SERVICE_REGISTRY.with(|registry| {
+        registry.borrow().chain_provider_counts(&known)
     })
 }
 
 #[update]
 #[candid_method(update)]
-fn record_payment_usage(
-    chain: String,
-    provider_id: String,
-    cost: u64,
-    success: bool,
-    response_time: f64,
-) -> Result<String, String> {
+fn deactivate_providers_by_chain(chain: String, include_multi_chain: bool) -> Result<u64, String> {
     is_authorized()?;
+    ensure_not_halted()?;
     
-    COST_OPTIMIZER.with(|optimizer| {
-        optimizer.borrow_mut().record_usage(&chain, &provider_id, cost, success, response_time);
-    });
+    Ok(SERVICE_REGISTRY.with(|registry| {
+        registry.borrow_mut().deactivate_providers_by_chain(&chain, include_multi_chain)
+    }))
+}
+
+// Payment Processing Methods
+#[update]
+#[candid_method(update)]
+fn submit_payment(mut payment: PaymentRequest) -> Result<String, String> {
+    is_authorized()?;
+    ensure_not_halted()?;
+    chains::validate_recipient(&payment.chain, &payment.recipient)?;
+    chains::validate_memo(&payment.chain, payment.memo.as_deref())?;
+    payment.submitter = Some(runtime::caller());
     
-    Ok("Usage recorded successfully".to_string())
+    PAYMENT_PROCESSOR.with(|processor| {
+        processor.borrow_mut().submit_payment(payment)
+    })
 }
 
 #[query]
 #[candid_method(query)]
-fn get_usage_metrics(time_window_seconds: u64) -> UsageMetrics {
-    COST_OPTIMIZER.with(|optimizer| {
-        optimizer.borrow().get_usage_metrics(time_window_seconds)
+fn find_similar_payments(recipient: String, amount: u64, window_seconds: u64) -> Vec<PaymentRequest> {
+    PAYMENT_PROCESSOR.with(|processor| {
+        processor.borrow().find_similar_payments(&recipient, amount, window_seconds).into_iter().cloned().collect()
+    })
+}
+
+#[query]
+#[candid_method(query)]
+fn list_deferred_payments() -> Vec<PaymentRequest> {
+    PAYMENT_PROCESSOR.with(|processor| {
+        processor.borrow().list_deferred_payments().into_iter().cloned().collect()
     })
 }
 
 #[update]
 #[candid_method(update)]
-fn update_optimization_settings(settings: OptimizationSettings) -> Result<String, String> {
+fn report_gas_price(chain: String, gas_price: u64) -> Result<String, String> {
     is_authorized()?;
-    
-    COST_OPTIMIZER.with(|optimizer| {
-        optimizer.borrow_mut().update_settings(settings);
+    ensure_not_halted()?;
+
+    PAYMENT_PROCESSOR.with(|processor| {
+        processor.borrow_mut().report_gas_price(&chain, gas_price)
     });
-    
-    Ok("Settings updated successfully".to_string())
+
+    Ok("Gas price reported successfully".to_string())
 }
 
-// Authorization Methods
 #[update]
 #[candid_method(update)]
-fn add_authorized_principal(principal: Principal) -> Result<String, String> {
+fn requeue_blocked_payments() -> Result<u64, String> {
     is_authorized()?;
-    
-    AUTHORIZED_PRINCIPALS.with(|principals| {
-        let mut principals = principals.borrow_mut();
-        if !principals.contains(&principal) {
-            principals.push(principal);
-        }
-    });
-    
-    Ok("Principal authorized successfully".to_string())
+    ensure_not_halted()?;
+
+    Ok(requeue_blocked_payments_internal())
 }
 
 #[update]
 #[candid_method(update)]
-fn remove_authorized_principal(principal: Principal) -> Result<String, String> {
+fn process_payment(payment_id: String) -> Result<String, String> {
     is_authorized()?;
+    ensure_not_halted()?;
     
-    AUTHORIZED_PRINCIPALS.with(|principals| {
-        let mut principals = principals.borrow_mut();
-        principals.retain(|&p| p != principal);
-    });
+    process_pending_payment(&payment_id)?;
     
-    Ok("Principal deauthorized successfully".to_string())
+    Ok("Payment processed successfully".to_string())
 }
 
-// Health Check
 #[query]
 #[candid_method(query)]
-fn health_check() -> String {
-    format!("Agentic Stablecoin Canister is healthy. Timestamp: {}", time())
+fn get_payment_status(payment_id: String) -> Option<PaymentStatus> {
+    PAYMENT_PROCESSOR.with(|processor| {
+        processor.borrow().get_payment_status(&payment_id)
+    })
 }
 
-// Auto-processing timer setup function
-fn setup_auto_processing() {
-    ic_cdk_timers::set_timer_interval(std::time::Duration::from_secs(60), || {
-        ic_cdk::spawn(async {
-            // Process pending payments automatically
-            let pending_payments = PAYMENT_PROCESSOR.with(|processor| {
-                processor.borrow().list_pending_payments().into_iter().cloned().collect::<Vec<_>>()
-            });
-            
-            for payment in pending_payments {
-                if matches!(payment.status, PaymentStatus::Pending) {
-                    let _ = PAYMENT_PROCESSOR.with(|processor| {
-                        processor.borrow_mut().process_payment(&payment.id)
-                    });
-                }
-            }
-        });
-    });
+#[query]
+#[candid_method(query)]
+fn get_failure_reason(payment_id: String) -> Option<String> {
+    PAYMENT_PROCESSOR.with(|processor| {
+        processor.borrow().failure_reason(&payment_id)
+    })
 }
 
-// Candid interface generation
-candid::export_service!();
+#[query]
+#[candid_method(query)]
+fn get_payment_receipt(payment_id: String) -> Option<TxReceipt> {
+    PAYMENT_PROCESSOR.with(|processor| {
+        processor.borrow().get_payment_receipt(&payment_id)
+    })
+}
 
-#[query(name = "__get_candid_interface_tmp_hack")]
-fn export_candid() -> String {
-    __export_service()
+#[query]
+#[candid_method(query)]
+fn pending_age_distribution() -> Vec<(String, u64)> {
+    PAYMENT_PROCESSOR.with(|processor| {
+        processor.borrow().pending_age_distribution()
+    })
+}
+
+#[query]
+#[candid_method(query)]
+fn volume_by_tag(tag: String, time_window_seconds: u64) -> u64 {
+    PAYMENT_PROCESSOR.with(|processor| {
+        processor.borrow().volume_by_tag(&tag, time_window_seconds)
+    })
+}
+
+#[query]
+#[candid_method(query)]
+fn estimate_batch_cost(count: u64, chain: String) -> u64 {
+    PAYMENT_PROCESSOR.with(|processor| {
+        processor.borrow().estimate_batch_cost(count, &chain)
+    })
+}
+
+#[query]
+#[candid_method(query)]
+fn average_retries_by_chain(time_window_seconds: u64) -> Vec<(String, f64)> {
+    PAYMENT_PROCESSOR.with(|processor| {
+        processor.borrow().average_retries_by_chain(time_window_seconds)
+    })
+}
+
+#[query]
+#[candid_method(query)]
+fn spend_by_principal(principal: Principal, time_window_seconds: u64) -> u64 {
+    PAYMENT_PROCESSOR.with(|processor| {
+        processor.borrow().spend_by_principal(&principal, time_window_seconds)
+    })
+}
+
+#[query]
+#[candid_method(query)]
+fn get_routing_decision(payment_id: String) -> Option<RoutingDecision> {
+    DECISION_LOG.with(|log| {
+        log.borrow().get(&payment_id)
+    })
+}
+
+#[query]
+#[candid_method(query)]
+fn reconciliation_report(start_ts: u64, end_ts: u64) -> ReconciliationReport {
+    PAYMENT_PROCESSOR.with(|processor| {
+        processor.borrow().reconciliation_report(start_ts, end_ts)
+    })
+}
+
+#[query]
+#[candid_method(query)]
+fn list_pending_payments() -> Vec<PaymentRequest> {
+    PAYMENT_PROCESSOR.with(|processor| {
+        processor.borrow().list_pending_payments().into_iter().cloned().collect()
+    })
+}
+
+#[update]
+#[candid_method(update)]
+fn cancel_payment(payment_id: String) -> Result<String, String> {
+    is_authorized()?;
+    ensure_not_halted()?;
+    
+    PAYMENT_PROCESSOR.with(|processor| {
+        processor.borrow_mut().cancel_payment(&payment_id)
+    })?;
+    
+    Ok("Payment cancelled successfully".to_string())
+}
+
+#[update]
+#[candid_method(update)]
+fn update_processor_settings(settings: ProcessorSettings) -> Result<String, String> {
+    is_authorized()?;
+    ensure_not_halted()?;
+    
+    PAYMENT_PROCESSOR.with(|processor| {
+        processor.borrow_mut().update_settings(settings);
+    });
+    
+    Ok("Processor settings updated successfully".to_string())
+}
+
+// Cost Optimization Methods
+#[query]
+#[candid_method(query)]
+fn optimize_payment_route(chain: String, amount: u64) -> Option<String> {
+    SERVICE_REGISTRY.with(|registry| {
+        COST_OPTIMIZER.with(|optimizer| {
+            optimizer.borrow().optimize_payment_route(&registry.borrow(), &chain, amount, None, None, &[])
+        })
+    })
+}
+
+#[query]
+#[candid_method(query)]
+fn optimize_payment_route_for_recipient(chain: String, amount: u64, recipient: String) -> Option<String> {
+    SERVICE_REGISTRY.with(|registry| {
+        COST_OPTIMIZER.with(|optimizer| {
+            optimizer.borrow().optimize_payment_route(&registry.borrow(), &chain, amount, Some(&recipient), None, &[])
+        })
+    })
+}
+
+#[query]
+#[candid_method(query)]
+fn plan_payment_route(chain: String, amount: u64, recipient: Option<String>) -> RoutePlan {
+    SERVICE_REGISTRY.with(|registry| {
+        COST_OPTIMIZER.with(|optimizer| {
+            optimizer.borrow().plan_payment_route(&registry.borrow(), &chain, amount, recipient.as_deref(), None, &[], &|candidate| {
+                PAYMENT_PROCESSOR.with(|processor| {
+                    processor.borrow().check_chain_rules(candidate, amount, recipient.as_deref(), None).is_ok()
+                })
+            })
+        })
+    })
+}
+
+#[query]
+#[candid_method(query)]
+fn compare_providers(a: String, b: String, chain: String, amount: u64) -> Result<ProviderComparison, String> {
+    SERVICE_REGISTRY.with(|registry| {
+        COST_OPTIMIZER.with(|optimizer| {
+            optimizer.borrow().compare_providers(&registry.borrow(), &a, &b, &chain, amount)
+        })
+    })
+}
+
+#[query]
+#[candid_method(query)]
+fn get_effective_cost(provider_id: String, chain: String, amount: u64) -> Option<u64> {
+    SERVICE_REGISTRY.with(|registry| {
+        let registry = registry.borrow();
+        let provider = registry.get_provider(&provider_id)?;
+        COST_OPTIMIZER.with(|optimizer| {
+            optimizer.borrow().get_effective_cost(provider, &chain, amount)
+        })
+    })
+}
+
+#[query]
+#[candid_method(query)]
+fn preview_rebalancing() -> RebalancingPreview {
+    COST_OPTIMIZER.with(|optimizer| {
+        optimizer.borrow().preview_rebalancing()
+    })
+}
+
+#[query]
+#[candid_method(query)]
+fn get_rebalancing_suggestions() -> Vec<RebalancingSuggestion> {
+    COST_OPTIMIZER.with(|optimizer| {
+        optimizer.borrow().suggest_chain_rebalancing()
+    })
+}
+
+#[query]
+#[candid_method(query)]
+fn stale_chains(max_age_seconds: u64) -> Vec<String> {
+    COST_OPTIMIZER.with(|optimizer| {
+        optimizer.borrow().stale_chains(max_age_seconds)
+    })
+}
+
+#[update]
+#[candid_method(update)]
+fn record_payment_usage(
+    chain: String,
+    provider_id: String,
+    cost: u64,
+    success: bool,
+    response_time: f64,
+) -> Result<String, String> {
+    is_authorized()?;
+    ensure_not_halted()?;
+    
+    let in_maintenance = SERVICE_REGISTRY.with(|registry| registry.borrow().in_maintenance(&provider_id));
+    let settings = COST_OPTIMIZER.with(|optimizer| {
+        let mut optimizer = optimizer.borrow_mut();
+        optimizer.record_usage(&chain, &provider_id, cost, success, response_time, in_maintenance);
+        optimizer.settings().clone()
+    });
+
+    let events = SERVICE_REGISTRY.with(|registry| {
+        let mut registry = registry.borrow_mut();
+        let mut events = registry.record_provider_outcome(
+            &provider_id,
+            success,
+            settings.warn_threshold,
+            settings.deactivate_threshold,
+            settings.reactivation_threshold,
+            settings.reactivation_min_samples,
+        );
+        events.extend(registry.record_latency_sample(&provider_id, response_time, settings.anomaly_sensitivity));
+        events
+    });
+    if events.iter().any(|event| matches!(event, EventKind::ProviderAutoReactivated { .. })) {
+        requeue_blocked_payments_internal();
+    }
+    EVENT_LOG.with(|log| {
+        let mut log = log.borrow_mut();
+        for event in events {
+            log.emit(event);
+        }
+    });
+    
+    Ok("Usage recorded successfully".to_string())
+}
+
+#[query]
+#[candid_method(query)]
+fn get_usage_metrics(time_window_seconds: u64) -> UsageMetrics {
+    COST_OPTIMIZER.with(|optimizer| {
+        optimizer.borrow().get_usage_metrics(time_window_seconds)
+    })
+}
+
+#[update]
+#[candid_method(update)]
+fn reset_chain_data(chain: String) -> Result<String, String> {
+    is_authorized()?;
+    ensure_not_halted()?;
+    
+    // Custom chains are only known through the data recorded for them
+    let had_data = COST_OPTIMIZER.with(|optimizer| {
+        optimizer.borrow_mut().reset_chain_data(&chain)
+    });
+    if !had_data && chains::get_known_chain(&chain).is_none() {
+        return Err(format!("Unknown chain {}", chain));
+    }
+    
+    Ok("Chain data reset successfully".to_string())
+}
+
+#[query]
+#[candid_method(query)]
+fn export_usage_csv(time_window_seconds: u64) -> Result<String, String> {
+    let (csv, next_offset) = COST_OPTIMIZER.with(|optimizer| {
+        optimizer.borrow().export_usage_csv(time_window_seconds, 0, usize::MAX, MAX_EXPORT_BYTES)
+    });
+    if next_offset.is_some() {
+        return Err("Export exceeds the response size limit; use export_usage_csv_page".to_string());
+    }
+    Ok(csv)
+}
+
+#[query]
+#[candid_method(query)]
+fn export_usage_csv_page(time_window_seconds: u64, offset: u64, limit: u64) -> CsvPage {
+    let (csv, next_offset) = COST_OPTIMIZER.with(|optimizer| {
+        let offset = usize::try_from(offset).unwrap_or(usize::MAX);
+        let limit = usize::try_from(limit).unwrap_or(usize::MAX);
+        optimizer.borrow().export_usage_csv(time_window_seconds, offset, limit, MAX_EXPORT_BYTES)
+    });
+    CsvPage { csv, next_offset }
+}
+
+#[query]
+#[candid_method(query)]
+fn provider_efficiency_ranking() -> Vec<(String, f64)> {
+    SERVICE_REGISTRY.with(|registry| {
+        COST_OPTIMIZER.with(|optimizer| {
+            optimizer.borrow().provider_efficiency_ranking(&registry.borrow())
+        })
+    })
+}
+
+#[query]
+#[candid_method(query)]
+fn get_provider_counters(provider_id: String) -> (u64, u64) {
+    COST_OPTIMIZER.with(|optimizer| {
+        optimizer.borrow().get_provider_counters(&provider_id)
+    })
+}
+
+#[query]
+#[candid_method(query)]
+fn get_metrics_by_chain(time_window_seconds: u64) -> Vec<(String, UsageMetrics)> {
+    COST_OPTIMIZER.with(|optimizer| {
+        optimizer.borrow().get_metrics_by_chain(time_window_seconds)
+    })
+}
+
+#[update]
+#[candid_method(update)]
+fn update_optimization_settings(settings: OptimizationSettings) -> Result<String, String> {
+    is_authorized()?;
+    ensure_not_halted()?;
+    
+    COST_OPTIMIZER.with(|optimizer| {
+        optimizer.borrow_mut().update_settings(settings)
+    })?;
+    requeue_blocked_payments_internal();
+    
+    Ok("Settings updated successfully".to_string())
+}
+
+#[update]
+#[candid_method(update)]
+fn save_settings_profile(name: String) -> Result<String, String> {
+    is_authorized()?;
+    ensure_not_halted()?;
+
+    COST_OPTIMIZER.with(|optimizer| {
+        optimizer.borrow_mut().save_settings_profile(&name)
+    })?;
+
+    Ok("Settings profile saved successfully".to_string())
+}
+
+#[update]
+#[candid_method(update)]
+fn load_settings_profile(name: String) -> Result<String, String> {
+    is_authorized()?;
+    ensure_not_halted()?;
+
+    COST_OPTIMIZER.with(|optimizer| {
+        optimizer.borrow_mut().load_settings_profile(&name)
+    })?;
+    requeue_blocked_payments_internal();
+
+    Ok("Settings profile loaded successfully".to_string())
+}
+
+#[query]
+#[candid_method(query)]
+fn list_settings_profiles() -> Vec<String> {
+    COST_OPTIMIZER.with(|optimizer| {
+        optimizer.borrow().list_settings_profiles()
+    })
+}
+
+// Event Log Methods
+#[query]
+#[candid_method(query)]
+fn get_recent_events(limit: u64) -> Vec<Event> {
+    EVENT_LOG.with(|log| {
+        log.borrow().recent(limit as usize)
+    })
+}
+
+#[query]
+#[candid_method(query)]
+fn recent_processing_trace(n: u64) -> Vec<ProcessingAttempt> {
+    PROCESSING_TRACE.with(|trace| {
+        trace.borrow().recent(n as usize)
+    })
+}
+
+fn grant_expired(principal: &Principal, now: u64) -> bool {
+    PRINCIPAL_EXPIRY.with(|expiry| {
+        expiry.borrow().get(principal).is_some_and(|&expires_at| now >= expires_at)
+    })
+}
+
+// Drops temporary grants that have expired
+fn cleanup_expired_grants() {
+    let now = time();
+    let expired: Vec<Principal> = PRINCIPAL_EXPIRY.with(|expiry| {
+        expiry.borrow()
+            .iter()
+            .filter(|(_, &expires_at)| now >= expires_at)
+            .map(|(principal, _)| *principal)
+            .collect()
+    });
+
+    for principal in expired {
+        revoke_principal(principal);
+    }
+}
+
+fn grant_principal(principal: Principal, expires_at: Option<u64>) {
+    AUTHORIZED_PRINCIPALS.with(|principals| {
+        let mut principals = principals.borrow_mut();
+        if !principals.contains(&principal) {
+            principals.push(principal);
+        }
+    });
+    PRINCIPAL_EXPIRY.with(|expiry| {
+        let mut expiry = expiry.borrow_mut();
+        match expires_at {
+            Some(expires_at) => expiry.insert(principal, expires_at),
+            None => expiry.remove(&principal),
+        };
+    });
+}
+
+// A temporary grant must not quietly put an expiry on a permanent one
+fn grant_temporary_principal(principal: Principal, expires_at: u64) -> Result<(), String> {
+    if has_permanent_grant(&principal) {
+        return Err("Principal already has a permanent grant".to_string());
+    }
+    grant_principal(principal, Some(expires_at));
+    Ok(())
+}
+
+fn has_permanent_grant(principal: &Principal) -> bool {
+    AUTHORIZED_PRINCIPALS.with(|principals| principals.borrow().contains(principal))
+        && PRINCIPAL_EXPIRY.with(|expiry| !expiry.borrow().contains_key(principal))
+}
+
+fn revoke_principal(principal: Principal) {
+    AUTHORIZED_PRINCIPALS.with(|principals| {
+        principals.borrow_mut().retain(|&p| p != principal);
+    });
+    PRINCIPAL_EXPIRY.with(|expiry| {
+        expiry.borrow_mut().remove(&principal);
+    });
+}
+
+// Authorization Methods
+#[update]
+#[candid_method(update)]
+fn add_authorized_principal(principal: Principal) -> Result<String, String> {
+    is_authorized()?;
+    
+    grant_principal(principal, None);
+    
+    Ok("Principal authorized successfully".to_string())
+}
+
+#[update]
+#[candid_method(update)]
+fn set_registration_keys(keys: Vec<Vec<u8>>) -> Result<String, String> {
+    is_admin()?;
+    ensure_not_halted()?;
+
+    REGISTRATION.with(|settings| {
+        let mut settings = settings.borrow_mut();
+        if keys.is_empty() && settings.verification_enabled {
+            return Err("Disable registration verification before removing its keys".to_string());
+        }
+        settings.trusted_keys = keys;
+        Ok(())
+    })?;
+
+    Ok("Registration keys updated successfully".to_string())
+}
+
+#[update]
+#[candid_method(update)]
+fn set_registration_verification(enabled: bool) -> Result<String, String> {
+    is_admin()?;
+    ensure_not_halted()?;
+
+    REGISTRATION.with(|settings| {
+        let mut settings = settings.borrow_mut();
+        if enabled && settings.trusted_keys.is_empty() {
+            return Err("No registration keys configured".to_string());
+        }
+        settings.verification_enabled = enabled;
+        Ok(())
+    })?;
+
+    Ok("Registration verification updated successfully".to_string())
+}
+
+#[query]
+#[candid_method(query)]
+fn registration_verification_enabled() -> bool {
+    REGISTRATION.with(|settings| settings.borrow().verification_enabled)
+}
+
+#[update]
+#[candid_method(update)]
+fn add_temporary_principal(principal: Principal, ttl_seconds: u64) -> Result<String, String> {
+    is_authorized()?;
+    
+    let expires_at = time().saturating_add(ttl_seconds.saturating_mul(1_000_000_000));
+    grant_temporary_principal(principal, expires_at)?;
+    cleanup_expired_grants();
+    
+    Ok("Temporary principal authorized successfully".to_string())
+}
+
+#[update]
+#[candid_method(update)]
+fn remove_authorized_principal(principal: Principal) -> Result<String, String> {
+    is_authorized()?;
+    
+    revoke_principal(principal);
+    
+    Ok("Principal deauthorized successfully".to_string())
+}
+
+#[query]
+#[candid_method(query)]
+fn list_authorized_principals() -> Result<Vec<Principal>, String> {
+    is_authorized()?;
+    
+    Ok(AUTHORIZED_PRINCIPALS.with(|principals| principals.borrow().clone()))
+}
+
+// Chain Configuration
+#[query]
+#[candid_method(query)]
+fn list_known_chains() -> Vec<KnownChain> {
+    chains::known_chains()
+}
+
+#[query]
+#[candid_method(query)]
+fn supported_chains_status() -> Vec<ChainStatus> {
+    SERVICE_REGISTRY.with(|registry| {
+        let registry = registry.borrow();
+        // Known chains plus any custom chain a provider has registered for, once
+        // each: the counts are keyed by chain
+        let chain_names: Vec<String> = registry
+            .chain_provider_counts(&chains::known_chains().into_iter().map(|c| c.name).collect::<Vec<_>>())
+            .into_iter()
+            .map(|(chain, _)| chain)
+            .collect();
+
+        COST_OPTIMIZER.with(|optimizer| {
+            optimizer.borrow().supported_chains_status(&registry, &chain_names)
+        })
+    })
+}
+
+// Health Check
+#[query]
+#[candid_method(query)]
+fn health_check() -> String {
+    format!("Agentic Stablecoin Canister is healthy. Timestamp: {}", time())
+}
+
+// Seconds until auto-processing clears the routable backlog at
+// max_payments_per_tick per timer tick; u64::MAX if it never will (halted, a zero
+// per-tick limit, or pending payments but none with an eligible provider)
+#[query]
+#[candid_method(query)]
+fn estimated_queue_drain_seconds() -> u64 {
+    let pending: Vec<PaymentRequest> = PAYMENT_PROCESSOR.with(|processor| {
+        processor.borrow()
+            .list_pending_payments()
+            .into_iter()
+            .filter(|p| !matches!(p.status, PaymentStatus::Blocked { .. }))
+            .cloned()
+            .collect()
+    });
+    if pending.is_empty() {
+        return 0;
+    }
+
+    let per_tick = PAYMENT_PROCESSOR.with(|processor| processor.borrow().settings().max_payments_per_tick);
+    let routable = pending.iter().filter(|p| estimate_settlement_cost(p) != u64::MAX).count() as u64;
+    if ensure_not_halted().is_err() || per_tick == 0 || routable == 0 {
+        return u64::MAX;
+    }
+
+    routable.div_ceil(per_tick).saturating_mul(AUTO_PROCESSING_INTERVAL_SECONDS)
+}
+
+#[query]
+#[candid_method(query)]
+fn readiness() -> Readiness {
+    let now = time();
+    let pending_backlog = PAYMENT_PROCESSOR.with(|processor| {
+        processor.borrow().list_pending_payments().len() as u64
+    });
+    let stale_providers = SERVICE_REGISTRY.with(|registry| {
+        registry.borrow().count_stale_providers(now, STALE_PROVIDER_NANOS)
+    });
+    let cycles_low = is_cycles_low(runtime::cycles_balance());
+
+    Readiness {
+        healthy: !cycles_low && pending_backlog <= MAX_PENDING_BACKLOG,
+        pending_backlog,
+        cycles_low,
+        stale_providers,
+        timestamp: now,
+    }
+}
+
+// (subsystem, healthy, detail) for the registry, processor, optimizer and
+// auto-processing timer
+#[query]
+#[candid_method(query)]
+fn subsystem_health() -> Vec<(String, bool, String)> {
+    let now = time();
+
+    let registry = SERVICE_REGISTRY.with(|registry| {
+        let registry = registry.borrow();
+        let providers = registry.list_providers();
+        let active = providers.iter().filter(|p| p.is_active).count();
+        let stale = registry.count_stale_providers(now, STALE_PROVIDER_NANOS);
+        (stale == 0, format!("{} providers, {} active, {} stale", providers.len(), active, stale))
+    });
+
+    let processor = PAYMENT_PROCESSOR.with(|processor| {
+        let processor = processor.borrow();
+        let backlog = processor.list_pending_payments().len() as u64;
+        let stalled = processor.stalled_count();
+        (
+            stalled == 0 && backlog <= MAX_PENDING_BACKLOG,
+            format!("{} pending, {} stalled in processing", backlog, stalled),
+        )
+    });
+
+    let optimizer = COST_OPTIMIZER.with(|optimizer| {
+        let stale = optimizer.borrow().stale_chains(STALE_PROVIDER_NANOS / 1_000_000_000);
+        if stale.is_empty() {
+            (true, "Chain cost data is current".to_string())
+        } else {
+            (false, format!("No recent cost data for {}", stale.join(", ")))
+        }
+    });
+
+    let last_tick = LAST_TIMER_TICK.with(|tick| *tick.borrow());
+    let overdue = now.saturating_sub(last_tick) > 2 * AUTO_PROCESSING_INTERVAL_SECONDS * 1_000_000_000;
+    let timer = match EMERGENCY_STOP.with(|stop| stop.borrow().clone()) {
+        Some(reason) => (false, format!("Paused by emergency stop: {}", reason)),
+        None if last_tick == 0 => (false, "Not started".to_string()),
+        None if overdue => (false, format!("Last ran {}s ago", now.saturating_sub(last_tick) / 1_000_000_000)),
+        None => (true, "Running".to_string()),
+    };
+
+    [("registry", registry), ("processor", processor), ("optimizer", optimizer), ("timer", timer)]
+        .into_iter()
+        .map(|(name, (healthy, detail))| (name.to_string(), healthy, detail))
+        .collect()
+}
+
+fn is_cycles_low(balance: u128) -> bool {
+    balance < LOW_CYCLES_THRESHOLD
+}
+
+// Whether routing may move a payment onto `chain`
+fn payment_fits_chain(payment: &PaymentRequest, chain: &str) -> bool {
+    PAYMENT_PROCESSOR.with(|processor| {
+        processor.borrow().check_chain_rules(chain, payment.amount, Some(&payment.recipient), payment.memo.as_deref()).is_ok()
+    })
+}
+
+// Shared by the process_payment endpoint and the auto-processing timer
+fn process_pending_payment(payment_id: &str) -> Result<(), String> {
+    let payment = PAYMENT_PROCESSOR.with(|processor| {
+        processor.borrow().get_pending_payment(payment_id).cloned()
+    }).ok_or("Payment not found")?;
+
+    let plan = SERVICE_REGISTRY.with(|registry| {
+        COST_OPTIMIZER.with(|optimizer| {
+            optimizer.borrow().plan_payment_route(&registry.borrow(), &payment.chain, payment.amount, Some(&payment.recipient), payment.token.as_deref(), payment.exclude_providers.as_deref().unwrap_or_default(), &|candidate| payment_fits_chain(&payment, candidate))
+        })
+    });
+    record_routing_decision(&payment, &plan);
+
+    let Some(provider_id) = plan.provider_id else {
+        let reason = format!("No eligible provider for chain {}", payment.chain);
+        PAYMENT_PROCESSOR.with(|processor| {
+            processor.borrow_mut().block_payment(payment_id, reason.clone())
+        });
+        record_processing_attempts(&[payment_id.to_string()], None, time());
+        return Err(reason);
+    };
+
+    let provider_policy = SERVICE_REGISTRY.with(|registry| {
+        registry.borrow().get_provider(&provider_id).and_then(|p| p.retry_policy.clone())
+    });
+
+    let started_at = time();
+    let (result, dispatched) = PAYMENT_PROCESSOR.with(|processor| {
+        let mut processor = processor.borrow_mut();
+        if let Err(e) = processor.assign_route(payment_id, &provider_id, &plan.chain) {
+            return (Err(e), false);
+        }
+        let dispatched = processor.is_dispatchable(payment_id);
+        (processor.process_payment(payment_id, provider_policy.as_ref()), dispatched)
+    });
+    // Only a dispatched payment draws on the provider's quota or counts as an
+    // attempt; scheduled, backing-off and gas-deferred payments are untouched
+    if dispatched {
+        SERVICE_REGISTRY.with(|registry| {
+            let mut registry = registry.borrow_mut();
+            registry.consume_quota(&provider_id);
+            registry.record_routed_activity(&provider_id);
+        });
+        record_processing_attempts(&[payment_id.to_string()], Some(&provider_id), started_at);
+        record_simulated_usage(&[payment_id.to_string()], &provider_id, &plan.chain);
+        raise_processing_alert();
+    }
+    result
+}
+
+// Emits ProcessingDegraded, and notifies alert_canister if configured, once the
+// processor has seen alert_after_consecutive_failures failures in a row
+fn raise_processing_alert() {
+    let Some(consecutive_failures) = PAYMENT_PROCESSOR.with(|processor| processor.borrow_mut().take_degraded_alert()) else {
+        return;
+    };
+
+    EVENT_LOG.with(|log| {
+        log.borrow_mut().emit(EventKind::ProcessingDegraded { consecutive_failures });
+    });
+    let alert_canister = PAYMENT_PROCESSOR.with(|processor| processor.borrow().settings().alert_canister);
+    if let Some(canister) = alert_canister {
+        // Best effort; the event log remains the record of the alert
+        let _ = ic_cdk::api::call::notify(canister, "processing_degraded", (consecutive_failures,));
+    }
+}
+
+// Appends each payment's post-attempt status to the processing trace. Latency is
+// simulated_latency_ms when set, otherwise the time since started_at.
+fn record_processing_attempts(payment_ids: &[String], provider_id: Option<&str>, started_at: u64) {
+    let simulated = PAYMENT_PROCESSOR.with(|processor| processor.borrow().settings().simulated_latency_ms);
+    let latency_ms = simulated.unwrap_or_else(|| time().saturating_sub(started_at) / 1_000_000);
+
+    for payment_id in payment_ids {
+        let Some(outcome) = PAYMENT_PROCESSOR.with(|processor| processor.borrow().get_payment_status(payment_id)) else {
+            continue;
+        };
+        PROCESSING_TRACE.with(|trace| {
+            trace.borrow_mut().record(payment_id, provider_id, outcome, latency_ms)
+        });
+    }
+}
+
+// With simulated_latency_ms set, each settlement attempt is fed to the optimizer
+// as usage so latency-dependent metrics can be exercised without real outcalls
+fn record_simulated_usage(payment_ids: &[String], provider_id: &str, chain: &str) {
+    let Some(latency_ms) = PAYMENT_PROCESSOR.with(|processor| processor.borrow().settings().simulated_latency_ms) else {
+        return;
+    };
+
+    for payment_id in payment_ids {
+        let (success, amount) = PAYMENT_PROCESSOR.with(|processor| {
+            let processor = processor.borrow();
+            let payment = processor.get_payment(payment_id);
+            (
+                payment.is_some_and(|p| matches!(p.status, PaymentStatus::Completed)),
+                payment.map(|p| p.amount).unwrap_or(0),
+            )
+        });
+        let (cost, in_maintenance) = SERVICE_REGISTRY.with(|registry| {
+            let registry = registry.borrow();
+            let cost = registry.get_provider(provider_id).map(|p| cost_optimizer::effective_cost(p, amount)).unwrap_or(0);
+            (cost, registry.in_maintenance(provider_id))
+        });
+        COST_OPTIMIZER.with(|optimizer| {
+            optimizer.borrow_mut().record_usage(chain, provider_id, cost, success, latency_ms as f64 / 1000.0, in_maintenance)
+        });
+    }
+}
+
+// Audit trail of the candidates scored for a payment, when the decision log is enabled
+fn record_routing_decision(payment: &PaymentRequest, plan: &RoutePlan) {
+    if !PAYMENT_PROCESSOR.with(|processor| processor.borrow().settings().decision_log_enabled) {
+        return;
+    }
+
+    let candidates = SERVICE_REGISTRY.with(|registry| {
+        COST_OPTIMIZER.with(|optimizer| {
+            optimizer.borrow().candidate_scores(&registry.borrow(), &plan.chain, payment.amount)
+        })
+    });
+    DECISION_LOG.with(|log| {
+        log.borrow_mut().record(&payment.id, &plan.chain, candidates, plan.provider_id.clone())
+    });
+}
+
+// Groups due payments by routed provider and chain and settles each group as one
+// multicall once it is full or has waited out the batch window
+fn process_payment_batches(payment_ids: Vec<String>) {
+    let max_size = PAYMENT_PROCESSOR.with(|processor| processor.borrow().batch_max_size());
+    COST_OPTIMIZER.with(|optimizer| optimizer.borrow_mut().begin_batch_round(max_size as u64));
+
+    // Groups keep the order in which selection produced them
+    let mut groups: Vec<((String, String), Vec<String>)> = Vec::new();
+    for payment_id in payment_ids {
+        let Some(payment) = PAYMENT_PROCESSOR.with(|processor| {
+            processor.borrow().get_pending_payment(&payment_id).cloned()
+        }) else {
+            continue;
+        };
+        let plan = SERVICE_REGISTRY.with(|registry| {
+            COST_OPTIMIZER.with(|optimizer| {
+                optimizer.borrow().plan_payment_route(&registry.borrow(), &payment.chain, payment.amount, Some(&payment.recipient), payment.token.as_deref(), payment.exclude_providers.as_deref().unwrap_or_default(), &|candidate| payment_fits_chain(&payment, candidate))
+            })
+        });
+        record_routing_decision(&payment, &plan);
+        match plan.provider_id {
+            Some(provider_id) => {
+                COST_OPTIMIZER.with(|optimizer| optimizer.borrow_mut().note_batch_member(&provider_id, &plan.chain));
+                let key = (provider_id, plan.chain);
+                match groups.iter_mut().find(|(k, _)| *k == key) {
+                    Some((_, ids)) => ids.push(payment_id),
+                    None => groups.push((key, vec![payment_id])),
+                }
+            }
+            None => {
+                let reason = format!("No eligible provider for chain {}", payment.chain);
+                PAYMENT_PROCESSOR.with(|processor| processor.borrow_mut().block_payment(&payment_id, reason));
+                record_processing_attempts(&[payment_id], None, time());
+            }
+        }
+    }
+    COST_OPTIMIZER.with(|optimizer| optimizer.borrow_mut().end_batch_round());
+
+    for ((provider_id, chain), ids) in groups {
+        for batch in ids.chunks(max_size) {
+            let ready = PAYMENT_PROCESSOR.with(|processor| processor.borrow().batch_ready(batch));
+            if !ready {
+                continue;
+            }
+
+            let provider_policy = SERVICE_REGISTRY.with(|registry| {
+                registry.borrow().get_provider(&provider_id).and_then(|p| p.retry_policy.clone())
+            });
+
+            // Payments beyond the provider's remaining quota stay pending and are
+            // routed again next round
+            let quota_left = SERVICE_REGISTRY.with(|registry| registry.borrow().remaining_quota(&provider_id));
+            let quota_left = quota_left.map_or(usize::MAX, |left| left as usize);
+
+            let started_at = time();
+            let dispatched: Vec<String> = PAYMENT_PROCESSOR.with(|processor| {
+                let mut processor = processor.borrow_mut();
+                let routed: Vec<String> = batch
+                    .iter()
+                    .filter(|payment_id| processor.assign_route(payment_id, &provider_id, &chain).is_ok())
+                    .cloned()
+                    .collect();
+                let dispatched: Vec<String> = routed
+                    .iter()
+                    .filter(|id| processor.is_dispatchable(id))
+                    .take(quota_left)
+                    .cloned()
+                    .collect();
+                let _ = processor.process_batch(&dispatched, provider_policy.as_ref());
+                dispatched
+            });
+            if dispatched.is_empty() {
+                continue;
+            }
+            SERVICE_REGISTRY.with(|registry| {
+                let mut registry = registry.borrow_mut();
+                for _ in &dispatched {
+                    registry.consume_quota(&provider_id);
+                }
+                registry.record_routed_activity(&provider_id);
+            });
+            record_processing_attempts(&dispatched, Some(&provider_id), started_at);
+            record_simulated_usage(&dispatched, &provider_id, &chain);
+            raise_processing_alert();
+        }
+    }
+}
+
+// Cost of the provider routing would currently pick; unroutable payments sort last
+fn estimate_settlement_cost(payment: &PaymentRequest) -> u64 {
+    SERVICE_REGISTRY.with(|registry| {
+        let registry = registry.borrow();
+        COST_OPTIMIZER.with(|optimizer| {
+            optimizer.borrow()
+                .optimize_payment_route(&registry, &payment.chain, payment.amount, Some(&payment.recipient), payment.token.as_deref(), payment.exclude_providers.as_deref().unwrap_or_default())
+                .and_then(|id| registry.get_provider(&id).map(|p| cost_optimizer::effective_cost(p, payment.amount)))
+                .unwrap_or(u64::MAX)
+        })
+    })
+}
+
+// Registers providers fetched by sync_providers_from. Imports go through normal
+// registration, so ids already present (or blocklisted, or over the per-chain cap)
+// are skipped rather than overwritten
+fn import_synced_providers(providers: Vec<ServiceProvider>) -> Result<u64, String> {
+    // An emergency stop may have been raised while the call was in flight
+    ensure_not_halted()?;
+
+    let max_providers_per_chain = COST_OPTIMIZER.with(|optimizer| {
+        optimizer.borrow().settings().max_providers_per_chain
+    });
+    let imported = REGISTRATION.with(|settings| SERVICE_REGISTRY.with(|registry| {
+        let settings = settings.borrow();
+        let mut registry = registry.borrow_mut();
+        providers
+            .into_iter()
+            .filter(|provider| !provider.id.is_empty())
+            .filter(|provider| registration::verify_registration(provider, &settings).is_ok())
+            .filter(|provider| registry.register_provider(provider.clone(), max_providers_per_chain).is_ok())
+            .count() as u64
+    }));
+    if imported > 0 {
+        requeue_blocked_payments_internal();
+    }
+
+    Ok(imported)
+}
+
+// Blocked payments get another routing attempt whenever providers or settings change
+fn requeue_blocked_payments_internal() -> u64 {
+    PAYMENT_PROCESSOR.with(|processor| {
+        processor.borrow_mut().requeue_blocked()
+    })
+}
+
+// Auto-processing timer setup function
+fn setup_auto_processing() {
+    LAST_TIMER_TICK.with(|tick| *tick.borrow_mut() = time());
+    ic_cdk_timers::set_timer_interval(std::time::Duration::from_secs(AUTO_PROCESSING_INTERVAL_SECONDS), || {
+        ic_cdk::spawn(async {
+            LAST_TIMER_TICK.with(|tick| *tick.borrow_mut() = time());
+            cleanup_expired_grants();
+            if ensure_not_halted().is_err() {
+                return;
+            }
+            PAYMENT_PROCESSOR.with(|processor| {
+                processor.borrow_mut().recover_stalled_payments(|provider_id| {
+                    SERVICE_REGISTRY.with(|registry| {
+                        registry.borrow().get_provider(provider_id).and_then(|p| p.retry_policy.clone())
+                    })
+                });
+            });
+            // Payments blocked on an exhausted quota can route again once it renews
+            if SERVICE_REGISTRY.with(|registry| registry.borrow_mut().reset_expired_quotas()) > 0 {
+                requeue_blocked_payments_internal();
+            }
+            raise_processing_alert();
+
+            // Process pending payments automatically in the configured order
+            let payment_ids = PAYMENT_PROCESSOR.with(|processor| {
+                processor.borrow().select_for_processing(estimate_settlement_cost)
+            });
+            
+            let batching = PAYMENT_PROCESSOR.with(|processor| processor.borrow().batch_max_size() > 1);
+            if batching {
+                process_payment_batches(payment_ids);
+            } else {
+                for payment_id in payment_ids {
+                    let _ = process_pending_payment(&payment_id);
+                }
+            }
+
+            let shifts = COST_OPTIMIZER.with(|optimizer| {
+                let mut optimizer = optimizer.borrow_mut();
+                optimizer.refresh_metrics_cache();
+                optimizer.apply_rebalancing()
+            });
+            EVENT_LOG.with(|log| {
+                let mut log = log.borrow_mut();
+                for shift in shifts {
+                    log.emit(EventKind::ChainPreferenceShifted {
+                        from_chain: shift.from_chain,
+                        to_chain: shift.to_chain,
+                        reason: shift.reason,
+                    });
+                }
+            });
+        });
+    });
+}
+
+// Candid interface generation
+candid::export_service!();
+
+#[query(name = "__get_candid_interface_tmp_hack")]
+fn export_candid() -> String {
+    __export_service()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EVM_RECIPIENT: &str = "0x00000000000000000000000000000000000000aa";
+
+    fn setup_simulated_latency(latency_ms: u64) {
+        SERVICE_REGISTRY.with(|registry| {
+            registry.borrow_mut().register_provider(ServiceProvider::for_test("eth", &["Ethereum"], 100), None).unwrap();
+        });
+        PAYMENT_PROCESSOR.with(|processor| {
+            let mut processor = processor.borrow_mut();
+            let mut settings = processor.settings().clone();
+            settings.simulated_latency_ms = Some(latency_ms);
+            processor.update_settings(settings);
+        });
+    }
+
+    fn submit(payment: PaymentRequest) {
+        PAYMENT_PROCESSOR.with(|processor| processor.borrow_mut().submit_payment(payment)).unwrap();
+    }
+
+    fn recorded_usage() -> UsageMetrics {
+        COST_OPTIMIZER.with(|optimizer| optimizer.borrow().get_usage_metrics(3600))
+    }
+
+    #[test]
+    fn simulated_latency_flows_into_usage_metrics() {
+        setup_simulated_latency(250);
+        submit(PaymentRequest::for_test("p1", "Ethereum", 1_000, EVM_RECIPIENT));
+        let _ = process_pending_payment("p1");
+
+        let metrics = recorded_usage();
+        assert_eq!(metrics.total_requests, 1);
+        assert!((metrics.average_response_time - 0.25).abs() < 1e-9);
+        assert_eq!(PROCESSING_TRACE.with(|trace| trace.borrow().recent(10).len()), 1);
+    }
+
+    #[test]
+    fn undispatched_payment_records_no_usage() {
+        setup_simulated_latency(250);
+        let mut scheduled = PaymentRequest::for_test("p1", "Ethereum", 1_000, EVM_RECIPIENT);
+        scheduled.execute_after = Some(time() + 3_600_000_000_000);
+        submit(scheduled);
+
+        assert_eq!(process_pending_payment("p1"), Err("Payment is scheduled for later".to_string()));
+        assert_eq!(recorded_usage().total_requests, 0);
+        assert_eq!(PROCESSING_TRACE.with(|trace| trace.borrow().recent(10).len()), 0);
+    }
+
+    #[test]
+    fn replacement_takes_over_payments_and_standing() {
+        register_service_provider(ServiceProvider::for_test("old", &["Ethereum"], 100)).unwrap();
+        let mut scheduled = PaymentRequest::for_test("p1", "Ethereum", 1_000, EVM_RECIPIENT);
+        scheduled.execute_after = Some(time() + 3_600_000_000_000);
+        submit(scheduled);
+        let mut excluding = PaymentRequest::for_test("p2", "Ethereum", 2_000, EVM_RECIPIENT);
+        excluding.exclude_providers = Some(vec!["old".to_string()]);
+        submit(excluding);
+        PAYMENT_PROCESSOR.with(|processor| processor.borrow_mut().assign_route("p1", "old", "Ethereum")).unwrap();
+        COST_OPTIMIZER.with(|optimizer| {
+            let mut optimizer = optimizer.borrow_mut();
+            let mut settings = optimizer.settings().clone();
+            settings.recipient_provider_pin.insert(EVM_RECIPIENT.to_string(), "old".to_string());
+            optimizer.update_settings(settings).unwrap();
+            optimizer.record_usage("Ethereum", "old", 100, true, 0.1, false);
+            optimizer.record_usage("Ethereum", "old", 100, false, 0.1, false);
+        });
+
+        assert_eq!(replace_provider("old".to_string(), ServiceProvider::for_test("new", &["Ethereum"], 90)), Ok("new".to_string()));
+
+        let payment = |id: &str| PAYMENT_PROCESSOR.with(|processor| processor.borrow().get_pending_payment(id).cloned()).unwrap();
+        assert_eq!(payment("p1").provider_id, "new");
+        assert_eq!(payment("p2").exclude_providers, Some(vec!["new".to_string()]));
+        COST_OPTIMIZER.with(|optimizer| {
+            let optimizer = optimizer.borrow();
+            assert_eq!(optimizer.settings().recipient_provider_pin.get(EVM_RECIPIENT), Some(&"new".to_string()));
+            assert_eq!(optimizer.get_provider_counters("new"), (1, 1));
+            assert_eq!(optimizer.get_provider_counters("old"), (0, 0));
+        });
+        let costs: Vec<u64> = get_provider_cost_history("new".to_string()).into_iter().map(|(_, cost)| cost).collect();
+        assert_eq!(costs, vec![100, 90]);
+    }
+
+    #[test]
+    fn batch_is_trimmed_to_remaining_quota() {
+        let mut provider = ServiceProvider::for_test("eth", &["Ethereum"], 100);
+        provider.quota = Some(Quota { total: 1, window_seconds: 3600 });
+        register_service_provider(provider).unwrap();
+        PAYMENT_PROCESSOR.with(|processor| {
+            let mut processor = processor.borrow_mut();
+            let settings = ProcessorSettings { batch_max_size: 10, ..processor.settings().clone() };
+            processor.update_settings(settings);
+        });
+        let ids: Vec<String> = (1..=3).map(|n| format!("p{}", n)).collect();
+        for (n, id) in ids.iter().enumerate() {
+            submit(PaymentRequest::for_test(id, "Ethereum", 1_000 * (n as u64 + 1), EVM_RECIPIENT));
+        }
+
+        process_payment_batches(ids.clone());
+
+        assert!(matches!(get_payment_status("p1".to_string()), Some(PaymentStatus::Completed)));
+        assert!(matches!(get_payment_status("p2".to_string()), Some(PaymentStatus::Pending)));
+        assert!(matches!(get_payment_status("p3".to_string()), Some(PaymentStatus::Pending)));
+        assert_eq!(SERVICE_REGISTRY.with(|registry| registry.borrow().remaining_quota("eth")), Some(0));
+    }
+
+    #[test]
+    fn provider_from_older_client_still_decodes() {
+        #[derive(candid::CandidType)]
+        struct LegacyServiceProvider {
+            id: String,
+            name: String,
+            api_endpoint: String,
+            supported_chains: Vec<String>,
+            cost_per_request: u64,
+            reliability_score: f64,
+            last_ping: u64,
+            is_active: bool,
+        }
+
+        let bytes = candid::encode_one(LegacyServiceProvider {
+            id: "eth".to_string(),
+            name: "Ethereum".to_string(),
+            api_endpoint: "https://eth.example".to_string(),
+            supported_chains: vec!["Ethereum".to_string()],
+            cost_per_request: 100,
+            reliability_score: 0.9,
+            last_ping: 0,
+            is_active: true,
+        })
+        .unwrap();
+        let provider: ServiceProvider = candid::decode_one(&bytes).unwrap();
+        assert!(provider.cost_tiers.is_none() && provider.supported_tokens.is_none());
+        assert!(register_service_provider(provider).is_ok());
+    }
+
+    #[test]
+    fn payment_from_older_client_still_decodes() {
+        #[derive(candid::CandidType)]
+        struct LegacyPaymentRequest {
+            id: String,
+            provider_id: String,
+            chain: String,
+            amount: u64,
+            recipient: String,
+            metadata: String,
+            timestamp: u64,
+            status: PaymentStatus,
+        }
+
+        let bytes = candid::encode_one(LegacyPaymentRequest {
+            id: "p1".to_string(),
+            provider_id: String::new(),
+            chain: "Ethereum".to_string(),
+            amount: 1_000,
+            recipient: EVM_RECIPIENT.to_string(),
+            metadata: String::new(),
+            timestamp: 0,
+            status: PaymentStatus::Pending,
+        })
+        .unwrap();
+        let payment: PaymentRequest = candid::decode_one(&bytes).unwrap();
+        assert!(payment.priority.is_none() && payment.allow_duplicate.is_none());
+        assert!(payment.tags.is_none() && payment.exclude_providers.is_none());
+        submit(payment);
+    }
+
+    #[test]
+    fn chain_status_lists_each_chain_once() {
+        register_service_provider(ServiceProvider::for_test("eth", &["Ethereum", "Custom"], 100)).unwrap();
+        register_service_provider(ServiceProvider::for_test("eth2", &["Ethereum", "Custom"], 200)).unwrap();
+
+        let chains: Vec<String> = supported_chains_status().into_iter().map(|status| status.chain).collect();
+        let mut unique = chains.clone();
+        unique.sort();
+        unique.dedup();
+        assert_eq!(chains.len(), unique.len());
+        assert!(chains.contains(&"Custom".to_string()) && chains.contains(&"Solana".to_string()));
+    }
+
+    #[test]
+    fn readiness_flags_low_cycles_and_stale_providers() {
+        register_service_provider(ServiceProvider::for_test("eth", &["Ethereum"], 100)).unwrap();
+        let ready = readiness();
+        assert!(ready.healthy && !ready.cycles_low);
+        assert_eq!(ready.stale_providers, 0);
+
+        runtime::set_cycles_balance(LOW_CYCLES_THRESHOLD);
+        assert!(!readiness().cycles_low);
+        runtime::set_cycles_balance(LOW_CYCLES_THRESHOLD - 1);
+        let ready = readiness();
+        assert!(ready.cycles_low && !ready.healthy);
+
+        crate::clock::advance_seconds(3601);
+        assert_eq!(readiness().stale_providers, 1);
+    }
+
+    #[test]
+    fn authorized_principals_are_listed_for_authorized_callers() {
+        let (alice, bob) = (Principal::from_slice(&[1]), Principal::from_slice(&[2]));
+        add_authorized_principal(alice).unwrap();
+        add_authorized_principal(bob).unwrap();
+        assert_eq!(list_authorized_principals(), Ok(vec![alice, bob]));
+
+        remove_authorized_principal(bob).unwrap();
+        assert_eq!(list_authorized_principals(), Ok(vec![alice]));
+        runtime::set_caller(bob);
+        assert_eq!(list_authorized_principals(), Err("Unauthorized".to_string()));
+    }
+
+    #[test]
+    fn unroutable_payment_is_blocked_until_a_provider_registers() {
+        submit(PaymentRequest::for_test("p1", "Ethereum", 1_000, EVM_RECIPIENT));
+
+        assert_eq!(process_pending_payment("p1"), Err("No eligible provider for chain Ethereum".to_string()));
+        assert!(matches!(get_payment_status("p1".to_string()), Some(PaymentStatus::Blocked { .. })));
+
+        register_service_provider(ServiceProvider::for_test("eth", &["Ethereum"], 100)).unwrap();
+        assert!(matches!(get_payment_status("p1".to_string()), Some(PaymentStatus::Pending)));
+    }
+
+    #[test]
+    fn routing_decision_is_recorded_when_enabled() {
+        register_service_provider(ServiceProvider::for_test("cheap", &["Ethereum"], 100)).unwrap();
+        register_service_provider(ServiceProvider::for_test("pricey", &["Ethereum"], 900)).unwrap();
+        submit(PaymentRequest::for_test("p1", "Ethereum", 1_000, EVM_RECIPIENT));
+        submit(PaymentRequest::for_test("p2", "Ethereum", 2_000, EVM_RECIPIENT));
+        let _ = process_pending_payment("p1");
+        assert!(get_routing_decision("p1".to_string()).is_none());
+
+        PAYMENT_PROCESSOR.with(|processor| {
+            let mut processor = processor.borrow_mut();
+            let settings = ProcessorSettings { decision_log_enabled: true, ..processor.settings().clone() };
+            processor.update_settings(settings);
+        });
+        let _ = process_pending_payment("p2");
+
+        let decision = get_routing_decision("p2".to_string()).unwrap();
+        assert_eq!(decision.selected_provider.as_deref(), Some("cheap"));
+        let candidates: Vec<&str> = decision.candidates.iter().map(|c| c.provider_id.as_str()).collect();
+        assert_eq!(candidates, vec!["cheap", "pricey"]);
+    }
+
+    #[test]
+    fn min_reliability_listing_filters_by_score_and_chain() {
+        for (id, chain, reliability) in [("eth-good", "Ethereum", 0.99), ("eth-poor", "Ethereum", 0.5), ("sol-good", "Solana", 0.99)] {
+            let mut provider = ServiceProvider::for_test(id, &[chain], 100);
+            provider.reliability_score = reliability;
+            register_service_provider(provider).unwrap();
+        }
+        let ids = |threshold: f64, chain: Option<&str>| {
+            let mut ids: Vec<String> = list_providers_min_reliability(threshold, chain.map(str::to_string))
+                .unwrap()
+                .into_iter()
+                .map(|p| p.id)
+                .collect();
+            ids.sort();
+            ids
+        };
+
+        assert_eq!(ids(0.9, None), vec!["eth-good", "sol-good"]);
+        assert_eq!(ids(0.9, Some("Ethereum")), vec!["eth-good"]);
+        assert_eq!(ids(0.0, Some("Ethereum")), vec!["eth-good", "eth-poor"]);
+        assert_eq!(list_providers_min_reliability(1.1, None).unwrap_err(), "Threshold must be between 0 and 1");
+    }
+
+    #[test]
+    fn synced_providers_are_imported_without_overwriting() {
+        register_service_provider(ServiceProvider::for_test("known", &["Ethereum"], 100)).unwrap();
+        submit(PaymentRequest::for_test("p1", "Solana", 1_000, "11111111111111111111111111111111"));
+        let _ = process_pending_payment("p1");
+        let synced = vec![
+            ServiceProvider::for_test("known", &["Ethereum"], 1),
+            ServiceProvider::for_test("", &["Ethereum"], 1),
+            ServiceProvider::for_test("sol", &["Solana"], 100),
+        ];
+
+        assert_eq!(import_synced_providers(synced.clone()), Ok(1));
+        assert_eq!(SERVICE_REGISTRY.with(|registry| registry.borrow().get_provider("known").unwrap().cost_per_request), 100);
+        assert!(matches!(get_payment_status("p1".to_string()), Some(PaymentStatus::Pending)));
+
+        EMERGENCY_STOP.with(|stop| *stop.borrow_mut() = Some("incident".to_string()));
+        assert_eq!(import_synced_providers(synced), Err("ServiceHalted: incident".to_string()));
+    }
+
+    #[test]
+    fn blocked_payments_can_be_requeued_on_demand() {
+        submit(PaymentRequest::for_test("p1", "Ethereum", 1_000, EVM_RECIPIENT));
+        let _ = process_pending_payment("p1");
+        SERVICE_REGISTRY.with(|registry| {
+            registry.borrow_mut().register_provider(ServiceProvider::for_test("eth", &["Ethereum"], 100), None).unwrap();
+        });
+        assert!(matches!(get_payment_status("p1".to_string()), Some(PaymentStatus::Blocked { .. })));
+
+        EMERGENCY_STOP.with(|stop| *stop.borrow_mut() = Some("incident".to_string()));
+        assert_eq!(requeue_blocked_payments(), Err("ServiceHalted: incident".to_string()));
+        EMERGENCY_STOP.with(|stop| *stop.borrow_mut() = None);
+
+        assert_eq!(requeue_blocked_payments(), Ok(1));
+        assert!(matches!(get_payment_status("p1".to_string()), Some(PaymentStatus::Pending)));
+        assert_eq!(requeue_blocked_payments(), Ok(0));
+    }
+
+    #[test]
+    fn listings_are_sorted_by_id() {
+        for id in ["zeta", "alpha", "mu"] {
+            register_service_provider(ServiceProvider::for_test(id, &["Ethereum"], 100)).unwrap();
+        }
+        for (n, id) in ["p3", "p1", "p2"].into_iter().enumerate() {
+            submit(PaymentRequest::for_test(id, "Ethereum", 1_000 + n as u64, EVM_RECIPIENT));
+        }
+
+        let providers: Vec<String> = list_service_providers().into_iter().map(|p| p.id).collect();
+        assert_eq!(providers, vec!["alpha", "mu", "zeta"]);
+        let payments: Vec<String> = list_pending_payments().into_iter().map(|p| p.id).collect();
+        assert_eq!(payments, vec!["p1", "p2", "p3"]);
+    }
+
+    #[test]
+    fn provider_chains_can_be_added_and_removed() {
+        register_service_provider(ServiceProvider::for_test("eth", &["Ethereum"], 100)).unwrap();
+        let chains = || SERVICE_REGISTRY.with(|registry| registry.borrow().get_provider("eth").unwrap().supported_chains.clone());
+
+        assert_eq!(add_provider_chain("eth".to_string(), "Dogecoin".to_string()), Err("Unknown chain Dogecoin".to_string()));
+        assert!(add_provider_chain("eth".to_string(), "Polygon".to_string()).is_ok());
+        assert_eq!(chains(), vec!["Ethereum", "Polygon"]);
+
+        assert_eq!(
+            remove_provider_chain("eth".to_string(), "Solana".to_string(), false),
+            Err("Provider does not support chain Solana".to_string())
+        );
+        assert!(remove_provider_chain("eth".to_string(), "Ethereum".to_string(), false).is_ok());
+        assert_eq!(
+            remove_provider_chain("eth".to_string(), "Polygon".to_string(), false),
+            Err("Cannot remove the provider's last chain without force".to_string())
+        );
+        assert!(remove_provider_chain("eth".to_string(), "Polygon".to_string(), true).is_ok());
+        assert!(chains().is_empty());
+    }
+
+    #[test]
+    fn processing_trace_records_blocked_and_routed_attempts() {
+        setup_simulated_latency(40);
+        submit(PaymentRequest::for_test("blocked", "Solana", 1_000, "11111111111111111111111111111111"));
+        submit(PaymentRequest::for_test("routed", "Ethereum", 2_000, EVM_RECIPIENT));
+        let _ = process_pending_payment("blocked");
+        let _ = process_pending_payment("routed");
+
+        let trace = recent_processing_trace(10);
+        assert_eq!(trace.len(), 2);
+        assert_eq!((trace[0].payment_id.as_str(), trace[0].provider_id.as_deref()), ("blocked", None));
+        assert!(matches!(trace[0].outcome, PaymentStatus::Blocked { .. }));
+        assert_eq!((trace[1].payment_id.as_str(), trace[1].provider_id.as_deref()), ("routed", Some("eth")));
+        assert_eq!(trace[1].latency_ms, 40);
+        assert_eq!(recent_processing_trace(1)[0].payment_id, "routed");
+    }
+
+    #[test]
+    fn queue_drain_estimate_counts_routable_ticks() {
+        assert_eq!(estimated_queue_drain_seconds(), 0);
+        submit(PaymentRequest::for_test("sol", "Solana", 1_000, "11111111111111111111111111111111"));
+        assert_eq!(estimated_queue_drain_seconds(), u64::MAX);
+
+        register_service_provider(ServiceProvider::for_test("eth", &["Ethereum"], 100)).unwrap();
+        PAYMENT_PROCESSOR.with(|processor| {
+            let mut processor = processor.borrow_mut();
+            let settings = ProcessorSettings { max_payments_per_tick: 2, ..processor.settings().clone() };
+            processor.update_settings(settings);
+        });
+        for n in 1..=3 {
+            submit(PaymentRequest::for_test(&format!("p{}", n), "Ethereum", 1_000 * n, EVM_RECIPIENT));
+        }
+        assert_eq!(estimated_queue_drain_seconds(), 2 * AUTO_PROCESSING_INTERVAL_SECONDS);
+
+        EMERGENCY_STOP.with(|stop| *stop.borrow_mut() = Some("incident".to_string()));
+        assert_eq!(estimated_queue_drain_seconds(), u64::MAX);
+    }
+
+    #[test]
+    fn subsystem_health_reports_each_subsystem() {
+        register_service_provider(ServiceProvider::for_test("eth", &["Ethereum"], 100)).unwrap();
+        let report = |name: &str| subsystem_health().into_iter().find(|(subsystem, ..)| subsystem == name).map(|(_, healthy, detail)| (healthy, detail)).unwrap();
+
+        assert_eq!(report("registry"), (true, "1 providers, 1 active, 0 stale".to_string()));
+        assert_eq!(report("processor"), (true, "0 pending, 0 stalled in processing".to_string()));
+        assert_eq!(report("optimizer"), (true, "Chain cost data is current".to_string()));
+        assert_eq!(report("timer"), (false, "Not started".to_string()));
+
+        LAST_TIMER_TICK.with(|tick| *tick.borrow_mut() = time());
+        assert_eq!(report("timer"), (true, "Running".to_string()));
+        clock::advance_seconds(2 * AUTO_PROCESSING_INTERVAL_SECONDS + 1);
+        assert_eq!(report("timer"), (false, format!("Last ran {}s ago", 2 * AUTO_PROCESSING_INTERVAL_SECONDS + 1)));
+        EMERGENCY_STOP.with(|stop| *stop.borrow_mut() = Some("incident".to_string()));
+        assert_eq!(report("timer"), (false, "Paused by emergency stop: incident".to_string()));
+    }
+
+    #[test]
+    fn temporary_grant_leaves_permanent_grant_alone() {
+        let principal = Principal::from_slice(&[7]);
+        grant_principal(principal, None);
+        assert_eq!(grant_temporary_principal(principal, time() + 60), Err("Principal already has a permanent grant".to_string()));
+        assert!(has_permanent_grant(&principal));
+
+        let temporary = Principal::from_slice(&[8]);
+        assert!(grant_temporary_principal(temporary, time() + 60).is_ok());
+        assert!(!has_permanent_grant(&temporary));
+        assert!(grant_temporary_principal(temporary, time() + 120).is_ok());
+    }
+
+    #[test]
+    fn blocklisting_requires_a_controller() {
+        let controller = Principal::from_slice(&[9]);
+        runtime::set_controller(controller);
+        assert_eq!(blocklist_provider("eth".to_string()), Err("Only a controller can do this".to_string()));
+        assert!(list_blocklisted_providers().is_empty());
+
+        runtime::set_caller(controller);
+        assert!(blocklist_provider("eth".to_string()).is_ok());
+        assert_eq!(list_blocklisted_providers(), vec!["eth".to_string()]);
+    }
+
+    #[test]
+    fn emergency_stop_blocks_mutations_but_not_reads() {
+        let controller = Principal::from_slice(&[9]);
+        runtime::set_controller(controller);
+        grant_principal(controller, None);
+        assert!(register_service_provider(ServiceProvider::for_test("eth", &["Ethereum"], 100)).is_ok());
+        assert_eq!(emergency_stop("incident".to_string()), Err("Only a controller can do this".to_string()));
+
+        runtime::set_caller(controller);
+        assert!(emergency_stop("incident".to_string()).is_ok());
+        assert_eq!(
+            register_service_provider(ServiceProvider::for_test("sol", &["Solana"], 100)),
+            Err("ServiceHalted: incident".to_string())
+        );
+        assert_eq!(get_emergency_stop(), Some("incident".to_string()));
+        assert_eq!(list_service_providers().len(), 1);
+
+        runtime::set_caller(Principal::anonymous());
+        assert_eq!(clear_emergency_stop(), Err("Only a controller can do this".to_string()));
+        runtime::set_caller(controller);
+        assert!(clear_emergency_stop().is_ok());
+        assert!(register_service_provider(ServiceProvider::for_test("sol", &["Solana"], 100)).is_ok());
+    }
+
+    #[test]
+    fn registration_verification_is_opt_in() {
+        let controller = Principal::from_slice(&[9]);
+        runtime::set_controller(controller);
+        assert_eq!(set_registration_keys(vec![b"key".to_vec()]), Err("Only a controller can do this".to_string()));
+
+        runtime::set_caller(controller);
+        grant_principal(controller, None);
+        assert_eq!(set_registration_verification(true), Err("No registration keys configured".to_string()));
+        assert!(set_registration_keys(vec![b"key".to_vec()]).is_ok());
+        assert!(!registration_verification_enabled());
+        assert!(register_service_provider(ServiceProvider::for_test("eth", &["Ethereum"], 100)).is_ok());
+
+        assert!(set_registration_verification(true).is_ok());
+        assert_eq!(set_registration_keys(Vec::new()), Err("Disable registration verification before removing its keys".to_string()));
+        assert_eq!(
+            register_service_provider(ServiceProvider::for_test("sol", &["Solana"], 100)),
+            Err("Registration signature required".to_string())
+        );
+        let mut forged = ServiceProvider::for_test("sol", &["Solana"], 100);
+        forged.registration_signature = Some(registration::sign_registration(&forged, b"other"));
+        assert_eq!(register_service_provider(forged), Err("Invalid registration signature".to_string()));
+
+        let mut signed = ServiceProvider::for_test("sol", &["Solana"], 100);
+        signed.registration_signature = Some(registration::sign_registration(&signed, b"key"));
+        assert!(register_service_provider(signed).is_ok());
+    }
 }
@@ -0,0 +1,51 @@
+//! Stable-memory layout. Every subsystem that persists state gets its own
+//! `MemoryId` from the shared `MemoryManager`; allocate new ids here only.
+
+use ic_stable_structures::memory_manager::MemoryId;
+
+pub const REGISTRY_MEMORY_ID: MemoryId = MemoryId::new(0);
+pub const PROCESSOR_MEMORY_ID: MemoryId = MemoryId::new(1);
+pub const OPTIMIZER_MEMORY_ID: MemoryId = MemoryId::new(2);
+pub const PRINCIPALS_MEMORY_ID: MemoryId = MemoryId::new(3);
+pub const EVENTS_MEMORY_ID: MemoryId = MemoryId::new(4);
+pub const DECISIONS_MEMORY_ID: MemoryId = MemoryId::new(5);
+
+pub const MEMORY_LAYOUT: [(&str, MemoryId); 6] = [
+    ("registry", REGISTRY_MEMORY_ID),
+    ("processor", PROCESSOR_MEMORY_ID),
+    ("optimizer", OPTIMIZER_MEMORY_ID),
+    ("principals", PRINCIPALS_MEMORY_ID),
+    ("events", EVENTS_MEMORY_ID),
+    ("decisions", DECISIONS_MEMORY_ID),
+];
+
+/// Fails if two subsystems were assigned the same memory id.
+pub fn validate_layout() -> Result<(), String> {
+    check_distinct(&MEMORY_LAYOUT)
+}
+
+fn check_distinct(layout: &[(&str, MemoryId)]) -> Result<(), String> {
+    for (i, (name, id)) in layout.iter().enumerate() {
+        if let Some((other, _)) = layout[i + 1..].iter().find(|(_, other_id)| other_id == id) {
+            return Err(format!("Memory id {:?} is assigned to both {} and {}", id, name, other));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn layout_ids_are_distinct() {
+        assert_eq!(validate_layout(), Ok(()));
+    }
+
+    #[test]
+    fn shared_id_is_reported() {
+        let layout = [("registry", MemoryId::new(0)), ("processor", MemoryId::new(1)), ("events", MemoryId::new(0))];
+        let error = check_distinct(&layout).unwrap_err();
+        assert!(error.contains("registry and events"), "{}", error);
+    }
+}
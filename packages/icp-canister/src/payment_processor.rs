@@ -1,39 +1,125 @@
-use crate::types::{PaymentRequest, PaymentStatus};
-use ic_cdk::api::time;
-use std::collections::HashMap;
+use crate::types::{
+    PaymentRequest, PaymentStatus, ProcessingStrategy, ProcessorSettings, ReconciliationReport, RetryPolicy, TxReceipt,
+    TxStatus,
+};
+use crate::chains;
+use crate::rng::Prng;
+use candid::Principal;
+use crate::clock::time;
+use std::collections::{HashMap, VecDeque};
+use std::hash::Hash;
+
+// Bounds on the per-key settlement series behind the windowed volume and
+// retry queries: samples older than the retention are dropped, and each key
+// keeps only its most recent MAX_SERIES_SAMPLES
+const SERIES_RETENTION_SECONDS: u64 = 30 * 24 * 3600;
+const MAX_SERIES_SAMPLES: usize = 1000;
 
 pub struct PaymentProcessor {
     pending_payments: HashMap<String, PaymentRequest>,
     completed_payments: HashMap<String, PaymentRequest>,
     retry_counts: HashMap<String, u32>,
+    // Earliest time (nanoseconds) a failed payment may be retried
+    retry_after: HashMap<String, u64>,
+    processing_started: HashMap<String, u64>,
+    // Settlement-content hash per submitted payment, and settle time per settled hash
+    content_hashes: HashMap<String, u64>,
+    settled_hashes: HashMap<u64, u64>,
+    // Tag -> (settle time, amount) of recent settled payments carrying it
+    settled_by_tag: HashMap<String, Vec<(u64, u64)>>,
+    // Submitter -> (settle time, amount) of recent settled payments they submitted
+    settled_by_principal: HashMap<Principal, Vec<(u64, u64)>>,
+    // Failed attempts since the last settlement, and an alert raised at the
+    // threshold that has not yet been taken
+    consecutive_failures: u64,
+    pending_alert: Option<u64>,
+    // Latest reported gas price per chain
+    gas_prices: HashMap<String, u64>,
+    // Chain -> (finish time, retries used) of recent payments that settled or
+    // terminally failed
+    retries_by_chain: HashMap<String, Vec<(u64, u32)>>,
+    // Source of retry jitter; each failure draws the next value
+    jitter: Prng,
+    settings: ProcessorSettings,
 }
 
 impl PaymentProcessor {
     pub fn new() -> Self {
+        Self::with_jitter_seed(0)
+    }
+
+    pub fn with_jitter_seed(seed: u64) -> Self {
         Self {
             pending_payments: HashMap::new(),
             completed_payments: HashMap::new(),
             retry_counts: HashMap::new(),
+            retry_after: HashMap::new(),
+            processing_started: HashMap::new(),
+            content_hashes: HashMap::new(),
+            settled_hashes: HashMap::new(),
+            settled_by_tag: HashMap::new(),
+            settled_by_principal: HashMap::new(),
+            consecutive_failures: 0,
+            pending_alert: None,
+            gas_prices: HashMap::new(),
+            retries_by_chain: HashMap::new(),
+            jitter: Prng::new(seed),
+            settings: ProcessorSettings::default(),
         }
     }
 
+    pub fn settings(&self) -> &ProcessorSettings {
+        &self.settings
+    }
+
+    pub fn update_settings(&mut self, settings: ProcessorSettings) {
+        self.settings = settings;
+    }
+
     pub fn submit_payment(&mut self, mut payment: PaymentRequest) -> Result<String, String> {
         if self.pending_payments.contains_key(&payment.id) || 
            self.completed_payments.contains_key(&payment.id) {
             return Err("Payment ID already exists".to_string());
         }
 
+        if let Some(&minimum) = self.settings.min_payment_amount.get(&payment.chain) {
+            if payment.amount < minimum {
+                return Err(format!("Amount {} is below the minimum of {} for chain {}", payment.amount, minimum, payment.chain));
+            }
+        }
+
+        self.check_spend_limit(&payment)?;
+
+        let limit = self.settings.max_metadata_bytes as usize;
+        let tag_bytes: usize = payment.tags.iter().flatten().map(|t| t.len()).sum();
+        let memo_bytes = payment.memo.as_ref().map_or(0, |m| m.len());
+        for (field, bytes) in [("metadata", payment.metadata.len()), ("tags", tag_bytes), ("memo", memo_bytes)] {
+            if bytes > limit {
+                return Err(format!("{} is {} bytes, over the max_metadata_bytes limit of {}", field, bytes, limit));
+            }
+        }
+
+        let hash = content_hash(&payment);
+        if payment.allow_duplicate != Some(true) {
+            self.check_replay(hash)?;
+        }
+
         payment.timestamp = time();
         payment.status = PaymentStatus::Pending;
+        payment.receipt = None;
+        payment.failure_reason = None;
         
         let payment_id = payment.id.clone();
         self.pending_payments.insert(payment_id.clone(), payment);
         self.retry_counts.insert(payment_id.clone(), 0);
+        self.content_hashes.insert(payment_id.clone(), hash);
 
         Ok(payment_id)
     }
 
-    pub fn process_payment(&mut self, payment_id: &str) -> Result<(), String> {
+    /// Processes a pending payment. `provider_policy` is the routed provider's
+    /// retry policy and takes precedence over the global one when present.
+    pub fn process_payment(&mut self, payment_id: &str, provider_policy: Option<&RetryPolicy>) -> Result<(), String> {
         // First check if payment exists
         if !self.pending_payments.contains_key(payment_id) {
             return Err("Payment not found".to_string());
@@ -41,28 +127,287 @@ impl PaymentProcessor {
 
         // Clone the payment to avoid borrowing issues
         let mut payment_clone = self.pending_payments.get(payment_id).unwrap().clone();
+        if self.gas_deferred(&payment_clone) {
+            return Err(format!("Gas price on {} is above max_gas_price; payment deferred", payment_clone.chain));
+        }
+        if !self.is_ready(&payment_clone, time()) {
+            return Err("Payment is scheduled for later".to_string());
+        }
         
         // Update status to processing
         payment_clone.status = PaymentStatus::Processing;
         self.pending_payments.insert(payment_id.to_string(), payment_clone.clone());
+        self.processing_started.insert(payment_id.to_string(), time());
         
         // Execute transaction using the clone
-        let success = self.execute_blockchain_transaction(&payment_clone);
+        let receipt = self.execute_blockchain_transaction(&payment_clone);
+        self.processing_started.remove(payment_id);
         
-        if success {
-            // Update payment status to completed
-            payment_clone.status = PaymentStatus::Completed;
-            
-            // Move to completed payments
-            self.pending_payments.remove(payment_id);
-            self.completed_payments.insert(payment_id.to_string(), payment_clone);
-            self.retry_counts.remove(payment_id);
+        if let Some(receipt) = receipt {
+            self.complete_payment(payment_id, payment_clone, receipt);
             Ok(())
         } else {
-            self.handle_payment_failure(payment_id)
+            let max_retries = provider_policy
+                .unwrap_or(&self.settings.retry_policy)
+                .max_retries;
+            self.handle_payment_failure(payment_id, max_retries, "Transaction rejected on-chain")
+        }
+    }
+
+    /// Settles several pending payments routed to the same provider and chain in
+    /// a single multicall transaction. Every payment in the batch shares the
+    /// outcome: all complete with the same receipt, or all take the failure path.
+    pub fn process_batch(&mut self, payment_ids: &[String], provider_policy: Option<&RetryPolicy>) -> Result<(), String> {
+        let now = time();
+        let mut batch: Vec<PaymentRequest> = payment_ids
+            .iter()
+            .filter_map(|id| self.pending_payments.get(id))
+            .filter(|p| self.is_ready(p, now))
+            .cloned()
+            .collect();
+        if batch.is_empty() {
+            return Err("No due payments in batch".to_string());
+        }
+
+        for payment in batch.iter_mut() {
+            payment.status = PaymentStatus::Processing;
+            self.pending_payments.insert(payment.id.clone(), payment.clone());
+            self.processing_started.insert(payment.id.clone(), now);
+        }
+
+        let receipt = self.execute_multicall_transaction(&batch);
+        for payment in &batch {
+            self.processing_started.remove(&payment.id);
+        }
+
+        match receipt {
+            Some(receipt) => {
+                for payment in batch {
+                    let payment_id = payment.id.clone();
+                    self.complete_payment(&payment_id, payment, receipt.clone());
+                }
+                Ok(())
+            }
+            None => {
+                let max_retries = provider_policy
+                    .unwrap_or(&self.settings.retry_policy)
+                    .max_retries;
+                let mut result = Ok(());
+                for payment in &batch {
+                    if let Err(e) = self.handle_payment_failure(&payment.id, max_retries, "Multicall transaction rejected on-chain") {
+                        result = Err(e);
+                    }
+                }
+                result
+            }
         }
     }
 
+    // Moves a settled payment to the completed set with its confirmation receipt
+    fn complete_payment(&mut self, payment_id: &str, mut payment: PaymentRequest, receipt: TxReceipt) {
+        payment.status = PaymentStatus::Completed;
+        payment.receipt = Some(receipt);
+
+        let now = time();
+        if let Some(hash) = self.content_hashes.remove(payment_id) {
+            self.settled_hashes.insert(hash, now);
+        }
+        for tag in payment.tags.iter().flatten() {
+            record_sample(&mut self.settled_by_tag, tag.clone(), now, payment.amount);
+        }
+        if let Some(submitter) = payment.submitter {
+            record_sample(&mut self.settled_by_principal, submitter, now, payment.amount);
+        }
+        self.record_retries(payment_id, &payment.chain);
+        self.pending_payments.remove(payment_id);
+        self.completed_payments.insert(payment_id.to_string(), payment);
+        self.retry_counts.remove(payment_id);
+        self.retry_after.remove(payment_id);
+        self.consecutive_failures = 0;
+    }
+
+    /// Takes the consecutive-failure count at which processing was last seen to
+    /// degrade. Raised once per run of failures, when it reaches
+    /// `alert_after_consecutive_failures`; a settlement ends the run.
+    pub fn take_degraded_alert(&mut self) -> Option<u64> {
+        self.pending_alert.take()
+    }
+
+    /// Reason for the payment's most recent failed attempt, whether it is
+    /// awaiting a retry or has terminally failed.
+    pub fn failure_reason(&self, payment_id: &str) -> Option<String> {
+        self.pending_payments
+            .get(payment_id)
+            .or_else(|| self.completed_payments.get(payment_id))
+            .and_then(|p| p.failure_reason.clone())
+    }
+
+    /// Projected cycles to settle `count` payments on `chain`: each transaction
+    /// (one per batch of up to `batch_max_size`) is signed once, submitted with
+    /// one outcall and polled for confirmation with the chain's poll count.
+    pub fn estimate_batch_cost(&self, count: u64, chain: &str) -> u64 {
+        let transactions = count.div_ceil(self.batch_max_size() as u64);
+        let polls = self.settings.confirmation_polls.get(chain).copied().unwrap_or(1);
+        let per_transaction = self.settings.cycles_per_signature
+            .saturating_add(self.settings.cycles_per_outcall.saturating_mul(polls.saturating_add(1)));
+        transactions.saturating_mul(per_transaction)
+    }
+
+    pub fn batch_max_size(&self) -> usize {
+        self.settings.batch_max_size.max(1) as usize
+    }
+
+    /// Whether a group of payments should be settled now: it is full, or its
+    /// oldest member has waited at least `batch_window_seconds`.
+    pub fn batch_ready(&self, payment_ids: &[String]) -> bool {
+        if payment_ids.len() >= self.batch_max_size() {
+            return true;
+        }
+        let window = self.settings.batch_window_seconds.saturating_mul(1_000_000_000);
+        let now = time();
+        payment_ids
+            .iter()
+            .filter_map(|id| self.pending_payments.get(id))
+            .any(|p| now.saturating_sub(p.timestamp) >= window)
+    }
+
+    /// Fails `Processing` payments that have been stuck longer than
+    /// `processing_timeout_seconds`, sending them through the normal retry path
+    /// so they become retryable (or terminally failed) instead of wedged. Each
+    /// payment is held to its routed provider's retry policy, as returned by
+    /// `provider_policy`, falling back to the global one.
+    pub fn recover_stalled_payments(&mut self, provider_policy: impl Fn(&str) -> Option<RetryPolicy>) -> u64 {
+        let stalled: Vec<String> = self.processing_started
+            .keys()
+            .filter(|id| self.is_stalled(id))
+            .cloned()
+            .collect();
+
+        for payment_id in &stalled {
+            self.processing_started.remove(payment_id);
+            let max_retries = self.pending_payments
+                .get(payment_id)
+                .and_then(|p| provider_policy(&p.provider_id))
+                .unwrap_or_else(|| self.settings.retry_policy.clone())
+                .max_retries;
+            let _ = self.handle_payment_failure(payment_id, max_retries, "Timed out waiting for confirmation");
+        }
+
+        stalled.len() as u64
+    }
+
+    /// Processing payments past their confirmation timeout that the next
+    /// recovery pass will fail.
+    pub fn stalled_count(&self) -> u64 {
+        self.processing_started.keys().filter(|id| self.is_stalled(id)).count() as u64
+    }
+
+    // Slow chains get longer to confirm; chains without an override use the
+    // global processing_timeout_seconds
+    fn is_stalled(&self, payment_id: &str) -> bool {
+        let timeout_seconds = self.pending_payments
+            .get(payment_id)
+            .and_then(|p| self.settings.confirmation_timeout_seconds.get(&p.chain))
+            .copied()
+            .unwrap_or(self.settings.processing_timeout_seconds);
+        let timeout = timeout_seconds.saturating_mul(1_000_000_000);
+        self.processing_started
+            .get(payment_id)
+            .is_some_and(|&started| time().saturating_sub(started) > timeout)
+    }
+
+    /// Rejects content that is already in flight or was settled within
+    /// `replay_window_seconds`.
+    fn check_replay(&mut self, hash: u64) -> Result<(), String> {
+        let now = time();
+        let window = self.settings.replay_window_seconds.saturating_mul(1_000_000_000);
+        self.settled_hashes.retain(|_, settled_at| now.saturating_sub(*settled_at) <= window);
+
+        let in_flight = self.pending_payments
+            .keys()
+            .any(|id| self.content_hashes.get(id) == Some(&hash));
+        if in_flight || self.settled_hashes.contains_key(&hash) {
+            return Err("Duplicate payment content within replay window".to_string());
+        }
+        Ok(())
+    }
+
+    pub fn get_pending_payment(&self, payment_id: &str) -> Option<&PaymentRequest> {
+        self.pending_payments.get(payment_id)
+    }
+
+    /// Checks a payment's recipient, memo and amount against `chain`'s rules, as
+    /// submission does for the chain it was submitted on.
+    pub fn check_chain_rules(&self, chain: &str, amount: u64, recipient: Option<&str>, memo: Option<&[u8]>) -> Result<(), String> {
+        if let Some(recipient) = recipient {
+            chains::validate_recipient(chain, recipient)?;
+        }
+        chains::validate_memo(chain, memo)?;
+        match self.settings.min_payment_amount.get(chain) {
+            Some(&minimum) if amount < minimum => {
+                Err(format!("Amount {} is below the minimum of {} for chain {}", amount, minimum, chain))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Records the provider (and possibly substituted chain) chosen by routing.
+    /// A substituted chain must accept the payment under check_chain_rules;
+    /// otherwise the payment is left untouched.
+    pub fn assign_route(&mut self, payment_id: &str, provider_id: &str, chain: &str) -> Result<(), String> {
+        let payment = self.pending_payments.get(payment_id).ok_or("Payment not found")?;
+        if payment.chain != chain {
+            self.check_chain_rules(chain, payment.amount, Some(&payment.recipient), payment.memo.as_deref())?;
+        }
+
+        if let Some(payment) = self.pending_payments.get_mut(payment_id) {
+            payment.provider_id = provider_id.to_string();
+            payment.chain = chain.to_string();
+        }
+        Ok(())
+    }
+
+    /// Points in-flight payments routed to `old_id`, and routing exclusions
+    /// naming it, at its successor `new_id`.
+    pub fn transfer_provider(&mut self, old_id: &str, new_id: &str) {
+        for payment in self.pending_payments.values_mut() {
+            if payment.provider_id == old_id {
+                payment.provider_id = new_id.to_string();
+            }
+            for excluded in payment.exclude_providers.iter_mut().flatten() {
+                if excluded == old_id {
+                    *excluded = new_id.to_string();
+                }
+            }
+        }
+    }
+
+    /// Parks a payment that no provider can currently serve. Blocked payments are
+    /// skipped by auto-processing and do not consume retries.
+    pub fn block_payment(&mut self, payment_id: &str, reason: String) {
+        if let Some(payment) = self.pending_payments.get_mut(payment_id) {
+            payment.status = PaymentStatus::Blocked { reason };
+        }
+    }
+
+    /// Returns every blocked payment to `Pending` so it is routed again.
+    pub fn requeue_blocked(&mut self) -> u64 {
+        let mut requeued = 0;
+        for payment in self.pending_payments.values_mut() {
+            if matches!(payment.status, PaymentStatus::Blocked { .. }) {
+                payment.status = PaymentStatus::Pending;
+                requeued += 1;
+            }
+        }
+        requeued
+    }
+
+    pub fn get_payment(&self, payment_id: &str) -> Option<&PaymentRequest> {
+        self.pending_payments
+            .get(payment_id)
+            .or_else(|| self.completed_payments.get(payment_id))
+    }
+
     pub fn get_payment_status(&self, payment_id: &str) -> Option<PaymentStatus> {
         if let Some(payment) = self.pending_payments.get(payment_id) {
             Some(payment.status.clone())
@@ -73,13 +418,169 @@ impl PaymentProcessor {
         }
     }
 
+    /// Counts of pending payments by time since submission: `<1m`, `1-5m`,
+    /// `5-30m` and `>30m`. Every bucket is always present.
+    pub fn pending_age_distribution(&self) -> Vec<(String, u64)> {
+        const MINUTE: u64 = 60 * 1_000_000_000;
+        let mut buckets = [0u64; 4];
+        let now = time();
+        for payment in self.pending_payments.values() {
+            let age = now.saturating_sub(payment.timestamp);
+            let bucket = match age {
+                a if a < MINUTE => 0,
+                a if a < 5 * MINUTE => 1,
+                a if a < 30 * MINUTE => 2,
+                _ => 3,
+            };
+            buckets[bucket] += 1;
+        }
+
+        ["<1m", "1-5m", "5-30m", ">30m"]
+            .iter()
+            .zip(buckets)
+            .map(|(label, count)| (label.to_string(), count))
+            .collect()
+    }
+
+    /// Settled volume of payments tagged `tag` over the last `time_window_seconds`.
+    pub fn volume_by_tag(&self, tag: &str, time_window_seconds: u64) -> u64 {
+        settled_volume(self.settled_by_tag.get(tag), time_window_seconds)
+    }
+
+    /// Settled volume of payments submitted by `principal` over the last
+    /// `time_window_seconds`.
+    pub fn spend_by_principal(&self, principal: &Principal, time_window_seconds: u64) -> u64 {
+        settled_volume(self.settled_by_principal.get(principal), time_window_seconds)
+    }
+
+    // Settled spend within the limit's window plus everything still pending
+    // counts against the submitter, so a burst cannot overrun the cap before
+    // any of it settles
+    fn check_spend_limit(&self, payment: &PaymentRequest) -> Result<(), String> {
+        let (Some(limit), Some(submitter)) = (&self.settings.principal_spend_limit, payment.submitter) else {
+            return Ok(());
+        };
+
+        let pending: u64 = self.pending_payments
+            .values()
+            .filter(|p| p.submitter == Some(submitter))
+            .fold(0, |total, p| total.saturating_add(p.amount));
+        let committed = self.spend_by_principal(&submitter, limit.window_seconds).saturating_add(pending);
+        if committed.saturating_add(payment.amount) > limit.amount {
+            return Err(format!("Payment would take {} past its spend limit of {}", submitter, limit.amount));
+        }
+        Ok(())
+    }
+
+    /// Reconciles payments submitted in `[start_ts, end_ts]` (nanoseconds) by
+    /// outcome. Anything not yet terminal counts as outstanding.
+    pub fn reconciliation_report(&self, start_ts: u64, end_ts: u64) -> ReconciliationReport {
+        let mut report = ReconciliationReport { start_ts, end_ts, ..Default::default() };
+
+        let in_range = self.pending_payments
+            .values()
+            .chain(self.completed_payments.values())
+            .filter(|p| p.timestamp >= start_ts && p.timestamp <= end_ts);
+        for payment in in_range {
+            let (count, volume) = match payment.status {
+                PaymentStatus::Completed => (&mut report.settled_count, &mut report.settled_volume),
+                PaymentStatus::Failed => (&mut report.failed_count, &mut report.failed_volume),
+                PaymentStatus::Cancelled => (&mut report.cancelled_count, &mut report.cancelled_volume),
+                _ => (&mut report.outstanding_count, &mut report.outstanding_volume),
+            };
+            *count += 1;
+            *volume = volume.saturating_add(payment.amount);
+            report.submitted_count += 1;
+            report.submitted_volume = report.submitted_volume.saturating_add(payment.amount);
+        }
+
+        report
+    }
+
+    /// Records the current gas price for `chain`. While it exceeds the chain's
+    /// `max_gas_price`, payments on the chain are held back.
+    pub fn report_gas_price(&mut self, chain: &str, gas_price: u64) {
+        self.gas_prices.insert(chain.to_string(), gas_price);
+    }
+
+    /// Pending payments held back by the gas ceiling, sorted by id.
+    pub fn list_deferred_payments(&self) -> Vec<&PaymentRequest> {
+        self.list_pending_payments()
+            .into_iter()
+            .filter(|p| matches!(p.status, PaymentStatus::Pending) && self.gas_deferred(p))
+            .collect()
+    }
+
+    /// Payments, pending or finished, to `recipient` for exactly `amount` that
+    /// were submitted within the last `window_seconds`, oldest first. Meant for
+    /// spotting accidental resubmissions before they happen.
+    pub fn find_similar_payments(&self, recipient: &str, amount: u64, window_seconds: u64) -> Vec<&PaymentRequest> {
+        let now = time();
+        let window = window_seconds.saturating_mul(1_000_000_000);
+        let mut similar: Vec<&PaymentRequest> = self.pending_payments
+            .values()
+            .chain(self.completed_payments.values())
+            .filter(|p| p.recipient == recipient && p.amount == amount)
+            .filter(|p| now.saturating_sub(p.timestamp) <= window)
+            .collect();
+        similar.sort_by(|a, b| a.timestamp.cmp(&b.timestamp).then(a.id.cmp(&b.id)));
+        similar
+    }
+
+    /// Pending payments, sorted by id.
     pub fn list_pending_payments(&self) -> Vec<&PaymentRequest> {
-        self.pending_payments.values().collect()
+        let mut payments: Vec<&PaymentRequest> = self.pending_payments.values().collect();
+        payments.sort_by(|a, b| a.id.cmp(&b.id));
+        payments
+    }
+
+    /// Due pending payments to process this tick, ordered by the configured
+    /// `ProcessingStrategy` and capped at `max_payments_per_tick`. `settle_cost`
+    /// estimates what a payment would cost to settle (used by `CostOptimized`).
+    pub fn select_for_processing(&self, settle_cost: impl Fn(&PaymentRequest) -> u64) -> Vec<String> {
+        let now = time();
+        let mut candidates: Vec<&PaymentRequest> = self.pending_payments
+            .values()
+            .filter(|p| matches!(p.status, PaymentStatus::Pending) && self.is_ready(p, now))
+            .collect();
+
+        // Oldest submission first breaks ties in every strategy
+        let fifo = |a: &PaymentRequest, b: &PaymentRequest| a.timestamp.cmp(&b.timestamp).then(a.id.cmp(&b.id));
+        match self.settings.strategy {
+            ProcessingStrategy::Fifo => candidates.sort_by(|a, b| fifo(a, b)),
+            ProcessingStrategy::Priority => {
+                candidates.sort_by(|a, b| b.priority.unwrap_or(0).cmp(&a.priority.unwrap_or(0)).then(fifo(a, b)))
+            }
+            ProcessingStrategy::CostOptimized => {
+                candidates.sort_by_cached_key(|p| (settle_cost(p), p.timestamp, p.id.clone()))
+            }
+            ProcessingStrategy::Deadline => {
+                // Payments without a deadline go after every payment that has one
+                candidates.sort_by(|a, b| {
+                    let a_deadline = a.execute_after.unwrap_or(u64::MAX);
+                    let b_deadline = b.execute_after.unwrap_or(u64::MAX);
+                    a_deadline.cmp(&b_deadline).then(fifo(a, b))
+                })
+            }
+        }
+        if self.settings.fair_by_submitter {
+            candidates = interleave_by_submitter(candidates);
+        }
+
+        candidates
+            .into_iter()
+            .take(self.settings.max_payments_per_tick as usize)
+            .map(|p| p.id.clone())
+            .collect()
     }
 
     pub fn cancel_payment(&mut self, payment_id: &str) -> Result<(), String> {
+        // A payment stuck in Processing past the timeout is no longer making progress
+        let stalled = self.is_stalled(payment_id);
         if let Some(payment) = self.pending_payments.get_mut(payment_id) {
-            if matches!(payment.status, PaymentStatus::Processing) {
+            // A payment waiting for its execute_after time has not started and can always be cancelled
+            let scheduled = !is_due(payment, time());
+            if !scheduled && !stalled && matches!(payment.status, PaymentStatus::Processing) {
                 return Err("Cannot cancel payment that is already processing".to_string());
             }
             
@@ -88,19 +589,28 @@ impl PaymentProcessor {
             self.pending_payments.remove(payment_id);
             self.completed_payments.insert(payment_id.to_string(), cancelled_payment);
             self.retry_counts.remove(payment_id);
+            self.retry_after.remove(payment_id);
+            self.content_hashes.remove(payment_id);
+            self.processing_started.remove(payment_id);
             Ok(())
         } else {
             Err("Payment not found".to_string())
         }
     }
 
-    fn execute_blockchain_transaction(&self, payment: &PaymentRequest) -> bool {
+    pub fn get_payment_receipt(&self, payment_id: &str) -> Option<TxReceipt> {
+        self.get_payment(payment_id).and_then(|p| p.receipt.clone())
+    }
+
+    fn execute_blockchain_transaction(&self, payment: &PaymentRequest) -> Option<TxReceipt> {
         // Simulate blockchain transaction
         // In real implementation, this would:
         // 1. Connect to the appropriate blockchain
         // 2. Prepare transaction data
         // 3. Submit transaction
         // 4. Wait for confirmation
+        // The memo, when present, is part of the transaction payload (e.g. ERC-20
+        // data field or ICRC memo), so it feeds into the simulated tx hash.
         
         // For simulation, return success 90% of the time
         use std::collections::hash_map::DefaultHasher;
@@ -109,30 +619,778 @@ impl PaymentProcessor {
         let mut hasher = DefaultHasher::new();
         payment.id.hash(&mut hasher);
         let hash = hasher.finish();
+        let tx_hash = match &payment.memo {
+            Some(memo) => {
+                let mut memo_hasher = DefaultHasher::new();
+                hash.hash(&mut memo_hasher);
+                memo.hash(&mut memo_hasher);
+                memo_hasher.finish()
+            }
+            None => hash,
+        };
         
-        (hash % 10) != 0 // 90% success rate
+        if hash % 10 == 0 {
+            return None; // 90% success rate
+        }
+
+        // Simulated confirmation receipt
+        Some(TxReceipt {
+            tx_hash: format!("0x{:016x}", tx_hash),
+            block_number: time() / 1_000_000_000,
+            gas_used: 21_000,
+            status: TxStatus::Success,
+        })
+    }
+
+    fn execute_multicall_transaction(&self, payments: &[PaymentRequest]) -> Option<TxReceipt> {
+        // Simulated multicall: one transaction carrying every transfer in the batch
+        use std::collections::hash_map::DefaultHasher;
+        use std::hash::{Hash, Hasher};
+
+        let mut hasher = DefaultHasher::new();
+        for payment in payments {
+            payment.id.hash(&mut hasher);
+            payment.memo.hash(&mut hasher);
+        }
+        let hash = hasher.finish();
+
+        if hash.is_multiple_of(10) {
+            return None; // 90% success rate
+        }
+
+        Some(TxReceipt {
+            tx_hash: format!("0x{:016x}", hash),
+            block_number: time() / 1_000_000_000,
+            // Base transaction cost plus a per-call overhead for each batched transfer
+            gas_used: 21_000u64.saturating_add(5_000u64.saturating_mul(payments.len() as u64)),
+            status: TxStatus::Success,
+        })
+    }
+
+    fn record_retries(&mut self, payment_id: &str, chain: &str) {
+        let retries = self.retry_counts.get(payment_id).copied().unwrap_or(0);
+        record_sample(&mut self.retries_by_chain, chain.to_string(), time(), retries);
+    }
+
+    /// Mean retries used by payments that finished (settled or terminally
+    /// failed) within the last `time_window_seconds`, per chain, sorted by chain.
+    pub fn average_retries_by_chain(&self, time_window_seconds: u64) -> Vec<(String, f64)> {
+        let now = time();
+        let window = time_window_seconds.saturating_mul(1_000_000_000);
+        let mut averages: Vec<(String, f64)> = self.retries_by_chain
+            .iter()
+            .filter_map(|(chain, finished)| {
+                let recent: Vec<u32> = finished
+                    .iter()
+                    .filter(|(finished_at, _)| now.saturating_sub(*finished_at) <= window)
+                    .map(|(_, retries)| *retries)
+                    .collect();
+                if recent.is_empty() {
+                    return None;
+                }
+                let total: u64 = recent.iter().map(|&r| r as u64).sum();
+                Some((chain.clone(), total as f64 / recent.len() as f64))
+            })
+            .collect();
+        averages.sort_by(|a, b| a.0.cmp(&b.0));
+        averages
+    }
+
+    /// Whether process_payment would dispatch the payment now, rather than return
+    /// early because it is scheduled, backing off or deferred on gas price.
+    pub fn is_dispatchable(&self, payment_id: &str) -> bool {
+        self.pending_payments.get(payment_id).is_some_and(|p| self.is_ready(p, time()))
     }
 
-    fn handle_payment_failure(&mut self, payment_id: &str) -> Result<(), String> {
+    // Scheduled time has passed, any retry backoff has elapsed and gas is not
+    // above the chain's ceiling
+    fn is_ready(&self, payment: &PaymentRequest, now: u64) -> bool {
+        is_due(payment, now)
+            && self.retry_after.get(&payment.id).is_none_or(|&after| now >= after)
+            && !self.gas_deferred(payment)
+    }
+
+    // Chains without a ceiling or without a reported price are never deferred
+    fn gas_deferred(&self, payment: &PaymentRequest) -> bool {
+        match (self.settings.max_gas_price.get(&payment.chain), self.gas_prices.get(&payment.chain)) {
+            (Some(ceiling), Some(price)) => price > ceiling,
+            _ => false,
+        }
+    }
+
+    // Backoff before the next attempt, spread by up to +/- retry_jitter_fraction
+    // so payments that failed together do not all retry on the same tick
+    fn retry_delay(&mut self) -> u64 {
+        let backoff = self.settings.retry_backoff_seconds.saturating_mul(1_000_000_000);
+        let jitter = self.settings.retry_jitter_fraction.clamp(0.0, 1.0);
+        let factor = 1.0 + jitter * (2.0 * self.jitter.next_f64() - 1.0);
+        (backoff as f64 * factor) as u64
+    }
+
+    fn handle_payment_failure(&mut self, payment_id: &str, max_retries: u32, reason: &str) -> Result<(), String> {
         let retry_count = self.retry_counts.get(payment_id).unwrap_or(&0);
+        if let Some(payment) = self.pending_payments.get_mut(payment_id) {
+            payment.failure_reason = Some(reason.to_string());
+        }
+        self.consecutive_failures = self.consecutive_failures.saturating_add(1);
+        let threshold = self.settings.alert_after_consecutive_failures;
+        if threshold > 0 && self.consecutive_failures == threshold {
+            self.pending_alert = Some(self.consecutive_failures);
+        }
         
-        if *retry_count < 3 {
+        if *retry_count < max_retries {
             // Retry the payment
-            self.retry_counts.insert(payment_id.to_string(), retry_count + 1);
+            self.retry_counts.insert(payment_id.to_string(), retry_count.saturating_add(1));
+            let retry_at = time().saturating_add(self.retry_delay());
+            self.retry_after.insert(payment_id.to_string(), retry_at);
             if let Some(payment) = self.pending_payments.get_mut(payment_id) {
                 payment.status = PaymentStatus::Pending;
             }
             Ok(())
         } else {
-            // Mark as failed after 3 retries
+            // Mark as failed once retries are exhausted
             if let Some(payment) = self.pending_payments.get_mut(payment_id) {
                 payment.status = PaymentStatus::Failed;
                 let failed_payment = payment.clone();
+                self.record_retries(payment_id, &failed_payment.chain);
                 self.pending_payments.remove(payment_id);
                 self.completed_payments.insert(payment_id.to_string(), failed_payment);
                 self.retry_counts.remove(payment_id);
+                self.retry_after.remove(payment_id);
+                self.content_hashes.remove(payment_id);
             }
             Err("Payment failed after maximum retries".to_string())
         }
     }
 }
+
+// Round-robins across submitters, keeping each submitter's payments in the
+// strategy's order; submitters take turns in order of their first payment
+fn interleave_by_submitter(ordered: Vec<&PaymentRequest>) -> Vec<&PaymentRequest> {
+    let mut queues: Vec<(Option<Principal>, VecDeque<&PaymentRequest>)> = Vec::new();
+    for payment in ordered {
+        match queues.iter_mut().find(|(submitter, _)| *submitter == payment.submitter) {
+            Some((_, queue)) => queue.push_back(payment),
+            None => queues.push((payment.submitter, VecDeque::from([payment]))),
+        }
+    }
+
+    let mut interleaved = Vec::new();
+    while !queues.is_empty() {
+        for (_, queue) in queues.iter_mut() {
+            interleaved.extend(queue.pop_front());
+        }
+        queues.retain(|(_, queue)| !queue.is_empty());
+    }
+    interleaved
+}
+
+// Appends a sample to `key`'s series, pruning it to the retention window and
+// to MAX_SERIES_SAMPLES. The other series are swept (dropping keys left empty)
+// only when `key` is new, so a write to an existing key touches just its own
+fn record_sample<K: Eq + Hash, V>(series: &mut HashMap<K, Vec<(u64, V)>>, key: K, now: u64, value: V) {
+    if !series.contains_key(&key) {
+        series.retain(|_, samples| {
+            prune_expired(samples, now);
+            !samples.is_empty()
+        });
+    }
+
+    let samples = series.entry(key).or_default();
+    prune_expired(samples, now);
+    samples.push((now, value));
+    let excess = samples.len().saturating_sub(MAX_SERIES_SAMPLES);
+    samples.drain(..excess);
+}
+
+fn prune_expired<V>(samples: &mut Vec<(u64, V)>, now: u64) {
+    let retention = SERIES_RETENTION_SECONDS.saturating_mul(1_000_000_000);
+    let expired = samples.partition_point(|(at, _)| now.saturating_sub(*at) > retention);
+    samples.drain(..expired);
+}
+
+// Sum of the (settle time, amount) entries within the last window
+fn settled_volume(settled: Option<&Vec<(u64, u64)>>, time_window_seconds: u64) -> u64 {
+    let now = time();
+    let window = time_window_seconds.saturating_mul(1_000_000_000);
+    settled
+        .map(|settled| {
+            settled
+                .iter()
+                .filter(|(settled_at, _)| now.saturating_sub(*settled_at) <= window)
+                .fold(0u64, |total, (_, amount)| total.saturating_add(*amount))
+        })
+        .unwrap_or(0)
+}
+
+// Scheduled payments become eligible once `execute_after` (nanoseconds) has passed
+fn is_due(payment: &PaymentRequest, now: u64) -> bool {
+    payment.execute_after.is_none_or(|execute_after| now >= execute_after)
+}
+
+// Hash over the fields that determine what is actually settled on-chain
+fn content_hash(payment: &PaymentRequest) -> u64 {
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+
+    let mut hasher = DefaultHasher::new();
+    payment.chain.hash(&mut hasher);
+    payment.amount.hash(&mut hasher);
+    payment.recipient.hash(&mut hasher);
+    payment.memo.hash(&mut hasher);
+    payment.token.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::SpendLimit;
+
+    const EVM_RECIPIENT: &str = "0x00000000000000000000000000000000000000aa";
+
+    // First payment of the form `<prefix>-<n>` whose simulated settlement
+    // succeeds or fails as asked
+    fn simulated_payment(prefix: &str, amount: u64, settles: bool) -> PaymentRequest {
+        let processor = PaymentProcessor::new();
+        (0..)
+            .map(|n| PaymentRequest::for_test(&format!("{}-{}", prefix, n), "Ethereum", amount, EVM_RECIPIENT))
+            .find(|payment| processor.execute_blockchain_transaction(payment).is_some() == settles)
+            .unwrap()
+    }
+
+    fn failing_payment(prefix: &str, amount: u64) -> PaymentRequest {
+        simulated_payment(prefix, amount, false)
+    }
+
+    #[test]
+    fn assign_route_rejects_chain_the_payment_is_invalid_on() {
+        let mut processor = PaymentProcessor::new();
+        processor.submit_payment(PaymentRequest::for_test("p1", "Ethereum", 1_000, EVM_RECIPIENT)).unwrap();
+
+        assert!(processor.assign_route("p1", "sol", "Solana").is_err());
+        let payment = processor.get_pending_payment("p1").unwrap();
+        assert_eq!((payment.chain.as_str(), payment.provider_id.as_str()), ("Ethereum", ""));
+
+        processor.assign_route("p1", "poly", "Polygon").unwrap();
+        assert_eq!(processor.get_pending_payment("p1").unwrap().chain, "Polygon");
+    }
+
+    #[test]
+    fn transfer_provider_reroutes_in_flight_payments() {
+        let mut processor = PaymentProcessor::new();
+        for (id, amount) in [("pending", 1_000), ("processing", 2_000), ("scheduled", 3_000), ("other", 4_000)] {
+            processor.submit_payment(PaymentRequest::for_test(id, "Ethereum", amount, EVM_RECIPIENT)).unwrap();
+            let provider = if id == "other" { "eth" } else { "old" };
+            processor.assign_route(id, provider, "Ethereum").unwrap();
+        }
+        processor.pending_payments.get_mut("processing").unwrap().status = PaymentStatus::Processing;
+        processor.pending_payments.get_mut("scheduled").unwrap().execute_after = Some(time() + 60_000_000_000);
+        processor.pending_payments.get_mut("other").unwrap().exclude_providers = Some(vec!["old".to_string(), "sol".to_string()]);
+
+        processor.transfer_provider("old", "new");
+
+        for id in ["pending", "processing", "scheduled"] {
+            assert_eq!(processor.get_pending_payment(id).unwrap().provider_id, "new");
+        }
+        let other = processor.get_pending_payment("other").unwrap();
+        assert_eq!(other.provider_id, "eth");
+        assert_eq!(other.exclude_providers, Some(vec!["new".to_string(), "sol".to_string()]));
+    }
+
+    #[test]
+    fn retry_jitter_is_bounded_and_reproducible() {
+        let delays = |seed: u64| -> Vec<u64> {
+            let mut processor = PaymentProcessor::with_jitter_seed(seed);
+            let settings = ProcessorSettings { retry_backoff_seconds: 10, retry_jitter_fraction: 0.5, ..processor.settings().clone() };
+            processor.update_settings(settings);
+            (0..50).map(|_| processor.retry_delay()).collect()
+        };
+
+        let first = delays(42);
+        assert_eq!(first, delays(42));
+        assert_ne!(first, delays(43));
+        assert!(first.iter().all(|&delay| (5_000_000_000..=15_000_000_000).contains(&delay)));
+        assert!(first.windows(2).any(|pair| pair[0] != pair[1]));
+    }
+
+    #[test]
+    fn spend_is_attributed_and_capped_per_principal() {
+        let mut processor = PaymentProcessor::new();
+        let settings = ProcessorSettings {
+            principal_spend_limit: Some(SpendLimit { amount: 5_000, window_seconds: 3600 }),
+            ..processor.settings().clone()
+        };
+        processor.update_settings(settings);
+        let (alice, bob) = (Principal::from_slice(&[1]), Principal::from_slice(&[2]));
+        let from = |submitter: Principal, id: &str, amount: u64| {
+            let mut payment = PaymentRequest::for_test(id, "Ethereum", amount, EVM_RECIPIENT);
+            payment.submitter = Some(submitter);
+            payment
+        };
+
+        processor.submit_payment(from(alice, "a1", 3_000)).unwrap();
+        processor.submit_payment(from(bob, "b1", 1_000)).unwrap();
+        assert!(processor.submit_payment(from(alice, "a2", 2_500)).is_err());
+        processor.process_payment("a1", None).unwrap();
+        processor.process_payment("b1", None).unwrap();
+        assert_eq!(processor.spend_by_principal(&alice, 3600), 3_000);
+        assert_eq!(processor.spend_by_principal(&bob, 3600), 1_000);
+        assert!(processor.submit_payment(from(alice, "a2", 2_500)).is_err());
+        assert!(processor.submit_payment(from(bob, "b2", 2_500)).is_ok());
+
+        crate::clock::advance_seconds(3601);
+        assert_eq!(processor.spend_by_principal(&alice, 3600), 0);
+        assert!(processor.submit_payment(from(alice, "a2", 4_000)).is_ok());
+    }
+
+    #[test]
+    fn priority_strategy_orders_by_priority_then_age_and_caps_the_tick() {
+        let mut processor = PaymentProcessor::new();
+        let settings = ProcessorSettings { strategy: ProcessingStrategy::Priority, max_payments_per_tick: 3, ..processor.settings().clone() };
+        processor.update_settings(settings);
+        for (n, (id, priority)) in [("none", None), ("mid-old", Some(5)), ("high", Some(9)), ("mid-new", Some(5))].into_iter().enumerate() {
+            let mut payment = PaymentRequest::for_test(id, "Ethereum", 1_000 + n as u64, EVM_RECIPIENT);
+            payment.priority = priority;
+            processor.submit_payment(payment).unwrap();
+            crate::clock::advance_seconds(1);
+        }
+
+        assert_eq!(processor.select_for_processing(|_| 0), vec!["high", "mid-old", "mid-new"]);
+    }
+
+    #[test]
+    fn scheduled_payment_waits_and_stays_cancellable() {
+        let mut processor = PaymentProcessor::new();
+        for (n, id) in ["later", "cancelled"].into_iter().enumerate() {
+            let mut payment = PaymentRequest::for_test(id, "Ethereum", 1_000 + n as u64, EVM_RECIPIENT);
+            payment.execute_after = Some(time() + 60_000_000_000);
+            processor.submit_payment(payment).unwrap();
+        }
+        processor.submit_payment(PaymentRequest::for_test("now", "Ethereum", 3_000, EVM_RECIPIENT)).unwrap();
+
+        assert_eq!(processor.select_for_processing(|_| 0), vec!["now"]);
+        assert_eq!(processor.process_payment("later", None), Err("Payment is scheduled for later".to_string()));
+
+        processor.pending_payments.get_mut("cancelled").unwrap().status = PaymentStatus::Processing;
+        assert_eq!(processor.cancel_payment("cancelled"), Ok(()));
+
+        crate::clock::advance_seconds(60);
+        assert_eq!(processor.select_for_processing(|_| 0), vec!["later", "now"]);
+    }
+
+    #[test]
+    fn fifo_cost_and_deadline_strategies_order_the_tick() {
+        let mut processor = PaymentProcessor::new();
+        let now = time();
+        for (n, (id, priority, execute_after)) in [("a", 1, None), ("b", 9, Some(now - 10)), ("c", 5, Some(now - 20))].into_iter().enumerate() {
+            let mut payment = PaymentRequest::for_test(id, "Ethereum", 1_000 + n as u64, EVM_RECIPIENT);
+            payment.priority = Some(priority);
+            payment.execute_after = execute_after;
+            processor.submit_payment(payment).unwrap();
+            crate::clock::advance_seconds(1);
+        }
+        let mut order = |strategy: ProcessingStrategy| {
+            let settings = ProcessorSettings { strategy, ..processor.settings().clone() };
+            processor.update_settings(settings);
+            processor.select_for_processing(|p| if p.id == "b" { 10 } else { 20 })
+        };
+
+        assert_eq!(order(ProcessingStrategy::Fifo), vec!["a", "b", "c"]);
+        assert_eq!(order(ProcessingStrategy::CostOptimized), vec!["b", "a", "c"]);
+        assert_eq!(order(ProcessingStrategy::Deadline), vec!["c", "b", "a"]);
+    }
+
+    #[test]
+    fn batch_waits_for_size_or_window_then_shares_one_receipt() {
+        let mut processor = PaymentProcessor::new();
+        let settings = ProcessorSettings { batch_max_size: 3, batch_window_seconds: 30, ..processor.settings().clone() };
+        processor.update_settings(settings);
+        let batch: Vec<PaymentRequest> = (0..)
+            .map(|n| (0..2).map(|i| PaymentRequest::for_test(&format!("b{}-{}", n, i), "Ethereum", 1_000 + i, EVM_RECIPIENT)).collect::<Vec<_>>())
+            .find(|batch| processor.execute_multicall_transaction(batch).is_some())
+            .unwrap();
+        let ids: Vec<String> = batch.iter().map(|p| p.id.clone()).collect();
+        for payment in batch {
+            processor.submit_payment(payment).unwrap();
+        }
+
+        assert!(!processor.batch_ready(&ids));
+        crate::clock::advance_seconds(30);
+        assert!(processor.batch_ready(&ids));
+
+        processor.process_batch(&ids, None).unwrap();
+        let receipts: Vec<TxReceipt> = ids.iter().map(|id| processor.get_payment_receipt(id).unwrap()).collect();
+        assert_eq!(receipts[0].tx_hash, receipts[1].tx_hash);
+        assert_eq!(receipts[0].gas_used, 31_000);
+    }
+
+    #[test]
+    fn reconciliation_buckets_sum_to_submitted_totals() {
+        let mut processor = PaymentProcessor::new();
+        processor.submit_payment(PaymentRequest::for_test("early", "Ethereum", 5, EVM_RECIPIENT)).unwrap();
+        crate::clock::advance_seconds(10);
+        let start = time();
+        let settled = simulated_payment("settled", 100, true);
+        let settled_id = settled.id.clone();
+        processor.submit_payment(settled).unwrap();
+        processor.submit_payment(PaymentRequest::for_test("cancelled", "Ethereum", 20, EVM_RECIPIENT)).unwrap();
+        processor.submit_payment(PaymentRequest::for_test("open", "Ethereum", 3, EVM_RECIPIENT)).unwrap();
+        processor.process_payment(&settled_id, None).unwrap();
+        processor.cancel_payment("cancelled").unwrap();
+
+        let report = processor.reconciliation_report(start, time());
+        assert_eq!((report.submitted_count, report.submitted_volume), (3, 123));
+        assert_eq!((report.settled_count, report.settled_volume), (1, 100));
+        assert_eq!((report.cancelled_count, report.cancelled_volume), (1, 20));
+        assert_eq!((report.outstanding_count, report.outstanding_volume), (1, 3));
+        assert_eq!(report.failed_count, 0);
+    }
+
+    #[test]
+    fn pending_ages_fall_into_fixed_buckets() {
+        let mut processor = PaymentProcessor::new();
+        for (n, wait) in [1_900, 600, 200, 0, 30].into_iter().enumerate() {
+            processor.submit_payment(PaymentRequest::for_test(&format!("p{}", n), "Ethereum", 1_000 + n as u64, EVM_RECIPIENT)).unwrap();
+            crate::clock::advance_seconds(wait);
+        }
+
+        let buckets = processor.pending_age_distribution();
+        let expected = [("<1m", 2), ("1-5m", 1), ("5-30m", 1), (">30m", 1)];
+        assert_eq!(buckets, expected.map(|(label, count)| (label.to_string(), count)).to_vec());
+    }
+
+    #[test]
+    fn chain_confirmation_timeout_overrides_the_global_one() {
+        let mut processor = PaymentProcessor::new();
+        let mut settings = ProcessorSettings { processing_timeout_seconds: 60, ..processor.settings().clone() };
+        settings.confirmation_timeout_seconds.insert("Polygon".to_string(), 600);
+        processor.update_settings(settings);
+        for (id, chain, amount) in [("eth", "Ethereum", 1_000), ("slow", "Polygon", 2_000)] {
+            processor.submit_payment(PaymentRequest::for_test(id, chain, amount, EVM_RECIPIENT)).unwrap();
+            processor.processing_started.insert(id.to_string(), time());
+        }
+
+        crate::clock::advance_seconds(61);
+        assert_eq!(processor.stalled_count(), 1);
+        assert!(processor.is_stalled("eth") && !processor.is_stalled("slow"));
+        crate::clock::advance_seconds(540);
+        assert_eq!(processor.stalled_count(), 2);
+    }
+
+    #[test]
+    fn submission_below_the_chain_minimum_is_rejected() {
+        let mut processor = PaymentProcessor::new();
+        let mut settings = processor.settings().clone();
+        settings.min_payment_amount.insert("Ethereum".to_string(), 1_000);
+        processor.update_settings(settings);
+
+        assert_eq!(
+            processor.submit_payment(PaymentRequest::for_test("small", "Ethereum", 999, EVM_RECIPIENT)),
+            Err("Amount 999 is below the minimum of 1000 for chain Ethereum".to_string())
+        );
+        assert!(processor.submit_payment(PaymentRequest::for_test("exact", "Ethereum", 1_000, EVM_RECIPIENT)).is_ok());
+        assert!(processor.submit_payment(PaymentRequest::for_test("other", "Polygon", 1, EVM_RECIPIENT)).is_ok());
+    }
+
+    #[test]
+    fn failure_reason_survives_retry_and_terminal_failure() {
+        let mut processor = PaymentProcessor::new();
+        let payment = failing_payment("rejected", 1_000);
+        let id = payment.id.clone();
+        processor.submit_payment(payment).unwrap();
+        assert_eq!(processor.failure_reason(&id), None);
+
+        let _ = processor.process_payment(&id, None);
+        assert!(matches!(processor.get_payment_status(&id), Some(PaymentStatus::Pending)));
+        assert_eq!(processor.failure_reason(&id).as_deref(), Some("Transaction rejected on-chain"));
+
+        let _ = processor.process_payment(&id, Some(&RetryPolicy { max_retries: 0 }));
+        assert!(matches!(processor.get_payment_status(&id), Some(PaymentStatus::Failed)));
+        assert_eq!(processor.failure_reason(&id).as_deref(), Some("Transaction rejected on-chain"));
+    }
+
+    #[test]
+    fn degraded_alert_fires_once_per_run_of_failures() {
+        let mut processor = PaymentProcessor::new();
+        let settings = ProcessorSettings { alert_after_consecutive_failures: 2, ..processor.settings().clone() };
+        processor.update_settings(settings);
+        let failing = failing_payment("failing", 1_000);
+        let settling = simulated_payment("settling", 2_000, true);
+        let (failing_id, settling_id) = (failing.id.clone(), settling.id.clone());
+        processor.submit_payment(failing).unwrap();
+        processor.submit_payment(settling).unwrap();
+        let policy = RetryPolicy { max_retries: 10 };
+        let fail = |processor: &mut PaymentProcessor| {
+            let _ = processor.process_payment(&failing_id, Some(&policy));
+            processor.take_degraded_alert()
+        };
+
+        assert_eq!(fail(&mut processor), None);
+        assert_eq!(fail(&mut processor), Some(2));
+        assert_eq!(fail(&mut processor), None);
+
+        processor.process_payment(&settling_id, None).unwrap();
+        assert_eq!(fail(&mut processor), None);
+        assert_eq!(fail(&mut processor), Some(2));
+    }
+
+    #[test]
+    fn payments_wait_out_gas_above_the_chain_ceiling() {
+        let mut processor = PaymentProcessor::new();
+        let mut settings = processor.settings().clone();
+        settings.max_gas_price.insert("Ethereum".to_string(), 50);
+        processor.update_settings(settings);
+        let payment = simulated_payment("gas", 1_000, true);
+        let id = payment.id.clone();
+        processor.submit_payment(payment).unwrap();
+        processor.submit_payment(PaymentRequest::for_test("poly", "Polygon", 2_000, EVM_RECIPIENT)).unwrap();
+        let deferred = |processor: &PaymentProcessor| processor.list_deferred_payments().into_iter().map(|p| p.id.clone()).collect::<Vec<_>>();
+        assert!(deferred(&processor).is_empty());
+
+        processor.report_gas_price("Ethereum", 80);
+        processor.report_gas_price("Polygon", 1_000);
+        assert_eq!(deferred(&processor), vec![id.clone()]);
+        assert_eq!(processor.select_for_processing(|_| 0), vec!["poly"]);
+        assert_eq!(processor.process_payment(&id, None), Err("Gas price on Ethereum is above max_gas_price; payment deferred".to_string()));
+
+        processor.report_gas_price("Ethereum", 50);
+        assert!(deferred(&processor).is_empty());
+        assert!(processor.process_payment(&id, None).is_ok());
+    }
+
+    #[test]
+    fn oversized_metadata_tags_or_memo_are_rejected() {
+        let mut processor = PaymentProcessor::new();
+        let mut settings = processor.settings().clone();
+        settings.max_metadata_bytes = 8;
+        processor.update_settings(settings);
+
+        let with = |id: &str, update: &dyn Fn(&mut PaymentRequest)| {
+            let mut payment = PaymentRequest::for_test(id, "Ethereum", 1_000, EVM_RECIPIENT);
+            update(&mut payment);
+            payment
+        };
+        assert_eq!(
+            processor.submit_payment(with("metadata", &|p| p.metadata = "123456789".to_string())),
+            Err("metadata is 9 bytes, over the max_metadata_bytes limit of 8".to_string())
+        );
+        assert_eq!(
+            processor.submit_payment(with("tags", &|p| p.tags = Some(vec!["12345".to_string(), "6789".to_string()]))),
+            Err("tags is 9 bytes, over the max_metadata_bytes limit of 8".to_string())
+        );
+        assert_eq!(
+            processor.submit_payment(with("memo", &|p| p.memo = Some(vec![0; 9]))),
+            Err("memo is 9 bytes, over the max_metadata_bytes limit of 8".to_string())
+        );
+        assert!(processor
+            .submit_payment(with("at-limit", &|p| {
+                p.metadata = "12345678".to_string();
+                p.tags = Some(vec!["1234".to_string(), "5678".to_string()]);
+                p.memo = Some(vec![0; 8]);
+            }))
+            .is_ok());
+    }
+
+    #[test]
+    fn average_retries_by_chain_covers_settled_and_failed_payments() {
+        let mut processor = PaymentProcessor::new();
+        let failing = failing_payment("failing", 1_000);
+        let settling = simulated_payment("settling", 2_000, true);
+        let (failing_id, settling_id) = (failing.id.clone(), settling.id.clone());
+        processor.submit_payment(failing).unwrap();
+        processor.submit_payment(settling).unwrap();
+        let policy = RetryPolicy { max_retries: 2 };
+
+        for _ in 0..3 {
+            let _ = processor.process_payment(&failing_id, Some(&policy));
+        }
+        assert_eq!(processor.average_retries_by_chain(60), vec![("Ethereum".to_string(), 2.0)]);
+        processor.process_payment(&settling_id, Some(&policy)).unwrap();
+        assert_eq!(processor.average_retries_by_chain(60), vec![("Ethereum".to_string(), 1.0)]);
+
+        crate::clock::advance_seconds(61);
+        assert!(processor.average_retries_by_chain(60).is_empty());
+    }
+
+    #[test]
+    fn fair_by_submitter_round_robins_the_tick() {
+        let mut processor = PaymentProcessor::new();
+        let (alice, bob) = (Principal::from_slice(&[1]), Principal::from_slice(&[2]));
+        for (n, (id, submitter)) in [("a1", alice), ("a2", alice), ("a3", alice), ("b1", bob), ("b2", bob)].into_iter().enumerate() {
+            let mut payment = PaymentRequest::for_test(id, "Ethereum", 1_000 + n as u64, EVM_RECIPIENT);
+            payment.submitter = Some(submitter);
+            processor.submit_payment(payment).unwrap();
+            crate::clock::advance_seconds(1);
+        }
+
+        assert_eq!(processor.select_for_processing(|_| 0), vec!["a1", "a2", "a3", "b1", "b2"]);
+        let settings = ProcessorSettings { fair_by_submitter: true, ..processor.settings().clone() };
+        processor.update_settings(settings);
+        assert_eq!(processor.select_for_processing(|_| 0), vec!["a1", "b1", "a2", "b2", "a3"]);
+    }
+
+    #[test]
+    fn batch_cost_counts_one_signature_and_outcalls_per_transaction() {
+        let mut processor = PaymentProcessor::new();
+        let mut settings = processor.settings().clone();
+        settings.batch_max_size = 4;
+        settings.cycles_per_signature = 1_000;
+        settings.cycles_per_outcall = 10;
+        settings.confirmation_polls.insert("Ethereum".to_string(), 3);
+        processor.update_settings(settings);
+
+        assert_eq!(processor.estimate_batch_cost(0, "Ethereum"), 0);
+        // Two transactions, each signed once, submitted once and polled three times
+        assert_eq!(processor.estimate_batch_cost(5, "Ethereum"), 2 * (1_000 + 4 * 10));
+        // Unlisted chains poll once
+        assert_eq!(processor.estimate_batch_cost(4, "Polygon"), 1_000 + 2 * 10);
+        assert_eq!(processor.estimate_batch_cost(u64::MAX, "Ethereum"), u64::MAX);
+    }
+
+    #[test]
+    fn similar_payments_match_recipient_and_amount_within_the_window() {
+        let mut processor = PaymentProcessor::new();
+        let settled = simulated_payment("settled", 1_000, true);
+        let settled_id = settled.id.clone();
+        processor.submit_payment(settled).unwrap();
+        processor.process_payment(&settled_id, None).unwrap();
+        crate::clock::advance_seconds(30);
+        processor.submit_payment(PaymentRequest::for_test("pending", "Polygon", 1_000, EVM_RECIPIENT)).unwrap();
+        processor.submit_payment(PaymentRequest::for_test("other-amount", "Polygon", 1_001, EVM_RECIPIENT)).unwrap();
+        processor.submit_payment(PaymentRequest::for_test("other-recipient", "Solana", 1_000, "11111111111111111111111111111111")).unwrap();
+
+        let similar = |window_seconds: u64| -> Vec<String> {
+            processor.find_similar_payments(EVM_RECIPIENT, 1_000, window_seconds).into_iter().map(|p| p.id.clone()).collect()
+        };
+        assert_eq!(similar(60), vec![settled_id, "pending".to_string()]);
+        assert_eq!(similar(10), vec!["pending"]);
+    }
+
+    #[test]
+    fn assign_route_enforces_target_chain_minimum() {
+        let mut processor = PaymentProcessor::new();
+        let mut settings = processor.settings().clone();
+        settings.min_payment_amount.insert("Polygon".to_string(), 5_000);
+        processor.update_settings(settings);
+        processor.submit_payment(PaymentRequest::for_test("p1", "Ethereum", 1_000, EVM_RECIPIENT)).unwrap();
+
+        assert!(processor.assign_route("p1", "poly", "Polygon").is_err());
+        assert!(processor.assign_route("p1", "eth", "Ethereum").is_ok());
+    }
+
+    #[test]
+    fn replay_rejects_identical_content_only() {
+        let mut processor = PaymentProcessor::new();
+        let base = PaymentRequest::for_test("p0", "Ethereum", 1_000, EVM_RECIPIENT);
+        processor.submit_payment(base.clone()).unwrap();
+
+        let mut same = base.clone();
+        same.id = "same".to_string();
+        same.metadata = "not part of the content".to_string();
+        assert_eq!(processor.submit_payment(same), Err("Duplicate payment content within replay window".to_string()));
+
+        let variants: [fn(&mut PaymentRequest); 5] = [
+            |p| p.chain = "Polygon".to_string(),
+            |p| p.amount += 1,
+            |p| p.recipient = "0x00000000000000000000000000000000000000bb".to_string(),
+            |p| p.memo = Some(b"invoice 7".to_vec()),
+            |p| p.token = Some("USDC".to_string()),
+        ];
+        for (i, vary) in variants.iter().enumerate() {
+            let mut payment = base.clone();
+            payment.id = format!("variant-{}", i);
+            vary(&mut payment);
+            assert!(processor.submit_payment(payment).is_ok(), "variant {} was rejected", i);
+        }
+
+        let mut allowed = base;
+        allowed.id = "allowed".to_string();
+        allowed.allow_duplicate = Some(true);
+        assert!(processor.submit_payment(allowed).is_ok());
+    }
+
+    #[test]
+    fn tag_volume_series_is_bounded() {
+        let mut processor = PaymentProcessor::new();
+        let settle = |processor: &mut PaymentProcessor, id: String, tag: &str, amount: u64| {
+            let mut payment = PaymentRequest::for_test(&id, "Ethereum", amount, EVM_RECIPIENT);
+            payment.tags = Some(vec![tag.to_string()]);
+            payment.allow_duplicate = Some(true);
+            processor.submit_payment(payment.clone()).unwrap();
+            processor.complete_payment(&id, payment, TxReceipt {
+                tx_hash: String::new(),
+                block_number: 0,
+                gas_used: 0,
+                status: TxStatus::Success,
+            });
+        };
+
+        settle(&mut processor, "old".to_string(), "stale", 5);
+        for i in 0..MAX_SERIES_SAMPLES + 10 {
+            settle(&mut processor, format!("p{}", i), "busy", 1);
+        }
+        assert_eq!(processor.settled_by_tag["busy"].len(), MAX_SERIES_SAMPLES);
+        assert_eq!(processor.volume_by_tag("stale", u64::MAX / 1_000_000_000), 5);
+
+        crate::clock::advance_seconds(SERIES_RETENTION_SECONDS + 1);
+        settle(&mut processor, "new".to_string(), "busy", 1);
+        assert_eq!(processor.settled_by_tag["busy"].len(), 1);
+        assert!(processor.settled_by_tag.contains_key("stale"));
+
+        settle(&mut processor, "first".to_string(), "fresh", 1);
+        assert!(!processor.settled_by_tag.contains_key("stale"));
+        assert_eq!(processor.settled_by_tag.len(), 2);
+    }
+
+    #[test]
+    fn provider_retry_policy_overrides_the_global_one() {
+        let mut processor = PaymentProcessor::new();
+        let strict = failing_payment("strict", 1_000);
+        let lenient = failing_payment("lenient", 2_000);
+        processor.submit_payment(strict.clone()).unwrap();
+        processor.submit_payment(lenient.clone()).unwrap();
+
+        assert_eq!(
+            processor.process_payment(&strict.id, Some(&RetryPolicy { max_retries: 0 })),
+            Err("Payment failed after maximum retries".to_string())
+        );
+        assert!(matches!(processor.get_payment_status(&strict.id), Some(PaymentStatus::Failed)));
+
+        assert_eq!(processor.process_payment(&lenient.id, None), Ok(()));
+        assert!(matches!(processor.get_payment_status(&lenient.id), Some(PaymentStatus::Pending)));
+        assert_eq!(processor.retry_counts[&lenient.id], 1);
+    }
+
+    #[test]
+    fn receipt_is_stored_on_settlement() {
+        let mut processor = PaymentProcessor::new();
+        let payment = simulated_payment("paid", 1_000, true);
+        processor.submit_payment(payment.clone()).unwrap();
+        assert!(processor.get_payment_receipt(&payment.id).is_none());
+
+        processor.process_payment(&payment.id, None).unwrap();
+        let receipt = processor.get_payment_receipt(&payment.id).unwrap();
+        assert!(receipt.tx_hash.starts_with("0x") && receipt.tx_hash.len() == 18);
+        assert!(matches!(receipt.status, TxStatus::Success));
+        assert!(processor.get_payment_receipt("missing").is_none());
+    }
+
+    #[test]
+    fn stalled_recovery_uses_routed_provider_policy() {
+        let mut processor = PaymentProcessor::new();
+        for (id, provider_id, amount) in [("strict", "no-retries", 1_000), ("lenient", "default", 2_000)] {
+            processor.submit_payment(PaymentRequest::for_test(id, "Ethereum", amount, EVM_RECIPIENT)).unwrap();
+            processor.assign_route(id, provider_id, "Ethereum").unwrap();
+            processor.processing_started.insert(id.to_string(), time());
+        }
+        crate::clock::advance_seconds(processor.settings().processing_timeout_seconds + 1);
+
+        let recovered = processor.recover_stalled_payments(|provider_id| {
+            (provider_id == "no-retries").then_some(RetryPolicy { max_retries: 0 })
+        });
+        assert_eq!(recovered, 2);
+        assert!(matches!(processor.get_payment_status("strict"), Some(PaymentStatus::Failed)));
+        assert!(processor.get_pending_payment("lenient").is_some());
+    }
+}
@@ -0,0 +1,50 @@
+use crate::types::PaymentStatus;
+use crate::clock::time;
+use std::collections::VecDeque;
+
+const MAX_ATTEMPTS: usize = 500;
+
+/// One processing attempt: the payment, the provider it was routed to (none
+/// when routing blocked it), and the payment's status afterwards.
+#[derive(Clone, Debug, candid::CandidType, serde::Serialize, serde::Deserialize)]
+pub struct ProcessingAttempt {
+    pub payment_id: String,
+    pub provider_id: Option<String>,
+    pub outcome: PaymentStatus,
+    pub latency_ms: u64,
+    pub timestamp: u64,
+}
+
+/// Cross-payment trace of the most recent processing attempts.
+pub struct ProcessingTrace {
+    attempts: VecDeque<ProcessingAttempt>,
+}
+
+impl ProcessingTrace {
+    pub fn new() -> Self {
+        Self {
+            attempts: VecDeque::new(),
+        }
+    }
+
+    pub fn record(&mut self, payment_id: &str, provider_id: Option<&str>, outcome: PaymentStatus, latency_ms: u64) {
+        self.attempts.push_back(ProcessingAttempt {
+            payment_id: payment_id.to_string(),
+            provider_id: provider_id.map(str::to_string),
+            outcome,
+            latency_ms,
+            timestamp: time(),
+        });
+
+        // Keep only the most recent attempts
+        if self.attempts.len() > MAX_ATTEMPTS {
+            self.attempts.pop_front();
+        }
+    }
+
+    /// Most recent attempts, newest last.
+    pub fn recent(&self, limit: usize) -> Vec<ProcessingAttempt> {
+        let skip = self.attempts.len().saturating_sub(limit);
+        self.attempts.iter().skip(skip).cloned().collect()
+    }
+}
@@ -0,0 +1,215 @@
+// Registration signatures: an HMAC-SHA256 tag over the provider's declared
+// configuration (see registration_message) keyed by one of the trusted
+// registration keys. Providers obtain their tag from whoever holds the key, so a
+// registration cannot be made up by the caller alone, and a signed record cannot
+// be replayed with different chains or prices.
+//
+// Trust model: the keys are shared secrets held by the canister and by the
+// operator issuing tags, not per-provider keys. Anyone holding a key can sign
+// any configuration, the tag says nothing about which provider asked for it, and
+// a leaked key must be rotated with set_registration_keys.
+use crate::service_registry::generate_provider_id;
+use crate::types::ServiceProvider;
+use sha2::{Digest, Sha256};
+
+const BLOCK_SIZE: usize = 64;
+
+// Verification is opt-in: keys alone do nothing until it is switched on, and
+// it cannot be switched on without keys (see set_registration_verification)
+pub struct RegistrationSettings {
+    pub verification_enabled: bool,
+    pub trusted_keys: Vec<Vec<u8>>,
+}
+
+impl RegistrationSettings {
+    pub const fn new() -> Self {
+        Self { verification_enabled: false, trusted_keys: Vec::new() }
+    }
+}
+
+/// Checks `provider.registration_signature` against the trusted keys. With
+/// verification off every registration is accepted.
+pub fn verify_registration(provider: &ServiceProvider, settings: &RegistrationSettings) -> Result<(), String> {
+    if !settings.verification_enabled {
+        return Ok(());
+    }
+
+    let signature = provider.registration_signature.as_deref().ok_or("Registration signature required")?;
+    if signed_by_trusted_key(&registration_message(provider), signature, &settings.trusted_keys) {
+        Ok(())
+    } else {
+        Err("Invalid registration signature".to_string())
+    }
+}
+
+/// Checks a provider manifest's HMAC-SHA256 signature over its exact bytes.
+/// Unlike single registrations, manifests are never accepted unsigned.
+pub fn verify_manifest(manifest: &[u8], signature: &[u8], trusted_keys: &[Vec<u8>]) -> Result<(), String> {
+    if trusted_keys.is_empty() {
+        return Err("No registration keys configured to verify the manifest".to_string());
+    }
+
+    if signed_by_trusted_key(manifest, signature, trusted_keys) {
+        Ok(())
+    } else {
+        Err("Invalid manifest signature".to_string())
+    }
+}
+
+fn signed_by_trusted_key(message: &[u8], signature: &[u8], trusted_keys: &[Vec<u8>]) -> bool {
+    trusted_keys.iter().any(|key| constant_time_eq(&hmac_sha256(key, message), signature))
+}
+
+// Canonical encoding of the configuration a registration tag covers: id (as
+// resolved at registration, so an omitted id signs the generated one), name,
+// api_endpoint, supported_chains, supported_tokens, cost_per_request, cost_tiers,
+// quota and capacity. Each field is followed by a NUL byte; lists are
+// comma-joined, cost tiers and quota are `a:b`, and absent values are empty.
+// Managed state (reliability, last ping, activation) is not covered.
+fn registration_message(provider: &ServiceProvider) -> Vec<u8> {
+    let id = if provider.id.is_empty() {
+        generate_provider_id(&provider.name, &provider.api_endpoint)
+    } else {
+        provider.id.clone()
+    };
+    let tiers: Vec<String> = provider.cost_tiers
+        .iter()
+        .flatten()
+        .map(|tier| format!("{}:{}", tier.min_amount, tier.cost_per_request))
+        .collect();
+    let fields = [
+        id,
+        provider.name.clone(),
+        provider.api_endpoint.clone(),
+        provider.supported_chains.join(","),
+        provider.supported_tokens.as_deref().unwrap_or_default().join(","),
+        provider.cost_per_request.to_string(),
+        tiers.join(","),
+        provider.quota.as_ref().map(|q| format!("{}:{}", q.total, q.window_seconds)).unwrap_or_default(),
+        provider.capacity.map(|c| c.to_string()).unwrap_or_default(),
+    ];
+
+    let mut message = Vec::new();
+    for field in fields {
+        message.extend_from_slice(field.as_bytes());
+        message.push(0);
+    }
+    message
+}
+
+#[cfg(test)]
+pub(crate) fn sign_registration(provider: &ServiceProvider, key: &[u8]) -> Vec<u8> {
+    hmac_sha256(key, &registration_message(provider))
+}
+
+// RFC 2104
+fn hmac_sha256(key: &[u8], message: &[u8]) -> Vec<u8> {
+    let mut block = [0u8; BLOCK_SIZE];
+    if key.len() > BLOCK_SIZE {
+        block[..32].copy_from_slice(&Sha256::digest(key));
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+
+    let mut inner = Sha256::new();
+    inner.update(block.map(|b| b ^ 0x36));
+    inner.update(message);
+    let mut outer = Sha256::new();
+    outer.update(block.map(|b| b ^ 0x5c));
+    outer.update(inner.finalize());
+    outer.finalize().to_vec()
+}
+
+// Comparison time does not depend on where the tags differ
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::CostTier;
+
+    const KEY: &[u8] = b"registration-key";
+
+    fn signed(mut provider: ServiceProvider, key: &[u8]) -> ServiceProvider {
+        provider.registration_signature = Some(sign_registration(&provider, key));
+        provider
+    }
+
+    fn enabled(keys: &[&[u8]]) -> RegistrationSettings {
+        RegistrationSettings { verification_enabled: true, trusted_keys: keys.iter().map(|key| key.to_vec()).collect() }
+    }
+
+    #[test]
+    fn hmac_matches_rfc_4231_vector() {
+        let tag = hmac_sha256(&[0x0b; 20], b"Hi There");
+        let hex: String = tag.iter().map(|b| format!("{:02x}", b)).collect();
+        assert_eq!(hex, "b0344c61d8db38535ca8afceaf0bf12b881dc200c9833da726e9376c2e32cff7");
+    }
+
+    #[test]
+    fn accepts_signed_registration() {
+        let provider = signed(ServiceProvider::for_test("p", &["ethereum"], 100), KEY);
+        assert!(verify_registration(&provider, &enabled(&[b"other", KEY])).is_ok());
+    }
+
+    #[test]
+    fn accepts_anything_while_verification_is_off() {
+        let unsigned = ServiceProvider::for_test("p", &["ethereum"], 100);
+        assert!(verify_registration(&unsigned, &RegistrationSettings::new()).is_ok());
+
+        let keys_only = RegistrationSettings { verification_enabled: false, trusted_keys: vec![KEY.to_vec()] };
+        assert!(verify_registration(&signed(unsigned.clone(), b"untrusted"), &keys_only).is_ok());
+        assert!(verify_registration(&unsigned, &keys_only).is_ok());
+    }
+
+    #[test]
+    fn rejects_missing_or_foreign_signature() {
+        let keys = enabled(&[KEY]);
+        let unsigned = ServiceProvider::for_test("p", &["ethereum"], 100);
+        assert_eq!(verify_registration(&unsigned, &keys), Err("Registration signature required".to_string()));
+
+        let foreign = signed(unsigned, b"untrusted");
+        assert_eq!(verify_registration(&foreign, &keys), Err("Invalid registration signature".to_string()));
+    }
+
+    #[test]
+    fn rejects_signed_record_with_altered_config() {
+        let keys = enabled(&[KEY]);
+        let original = signed(ServiceProvider::for_test("p", &["ethereum"], 100), KEY);
+        let tampered: [fn(&mut ServiceProvider); 5] = [
+            |p| p.id = "q".to_string(),
+            |p| p.supported_chains.push("polygon".to_string()),
+            |p| p.cost_per_request = 1,
+            |p| p.cost_tiers = Some(vec![CostTier { min_amount: 0, cost_per_request: 1 }]),
+            |p| p.capacity = Some(10),
+        ];
+        for tamper in tampered {
+            let mut provider = original.clone();
+            tamper(&mut provider);
+            assert!(verify_registration(&provider, &keys).is_err());
+        }
+    }
+
+    #[test]
+    fn signature_with_omitted_id_covers_generated_id() {
+        let mut provider = ServiceProvider::for_test("p", &["ethereum"], 100);
+        provider.id = String::new();
+        let provider = signed(provider, KEY);
+        let mut registered = provider.clone();
+        registered.id = generate_provider_id(&provider.name, &provider.api_endpoint);
+        assert!(verify_registration(&provider, &enabled(&[KEY])).is_ok());
+        assert!(verify_registration(&registered, &enabled(&[KEY])).is_ok());
+    }
+
+    #[test]
+    fn manifest_must_be_signed_over_exact_bytes() {
+        let manifest = br#"[{"id":"p"}]"#;
+        let signature = hmac_sha256(KEY, manifest);
+        let keys = [KEY.to_vec()];
+        assert!(verify_manifest(manifest, &signature, &keys).is_ok());
+        assert!(verify_manifest(br#"[{"id":"q"}]"#, &signature, &keys).is_err());
+        assert!(verify_manifest(manifest, &signature, &[]).is_err());
+    }
+}
@@ -0,0 +1,70 @@
+// Caller identity and canister state from the system API. Unit tests run
+// outside a canister and read settable values instead, like crate::clock.
+use candid::Principal;
+
+#[cfg(not(test))]
+pub fn caller() -> Principal {
+    ic_cdk::caller()
+}
+
+#[cfg(not(test))]
+pub fn is_controller(principal: &Principal) -> bool {
+    ic_cdk::api::is_controller(principal)
+}
+
+#[cfg(not(test))]
+pub fn cycles_balance() -> u128 {
+    ic_cdk::api::canister_balance128()
+}
+
+#[cfg(test)]
+thread_local! {
+    static CALLER: std::cell::Cell<Principal> = const { std::cell::Cell::new(Principal::anonymous()) };
+    static CONTROLLER: std::cell::Cell<Option<Principal>> = const { std::cell::Cell::new(None) };
+    static BALANCE: std::cell::Cell<u128> = const { std::cell::Cell::new(u128::MAX) };
+}
+
+#[cfg(test)]
+pub fn caller() -> Principal {
+    CALLER.with(|caller| caller.get())
+}
+
+#[cfg(test)]
+pub fn is_controller(principal: &Principal) -> bool {
+    CONTROLLER.with(|controller| controller.get() == Some(*principal))
+}
+
+#[cfg(test)]
+pub fn cycles_balance() -> u128 {
+    BALANCE.with(|balance| balance.get())
+}
+
+#[cfg(test)]
+pub fn set_caller(principal: Principal) {
+    CALLER.with(|caller| caller.set(principal));
+}
+
+#[cfg(test)]
+pub fn set_controller(principal: Principal) {
+    CONTROLLER.with(|controller| controller.set(Some(principal)));
+}
+
+#[cfg(test)]
+pub fn set_cycles_balance(cycles: u128) {
+    BALANCE.with(|balance| balance.set(cycles));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_controller_status_follows_the_configured_principal() {
+        let controller = Principal::from_slice(&[7]);
+        assert!(!is_controller(&controller));
+        set_controller(controller);
+        set_caller(controller);
+        assert!(is_controller(&caller()));
+        assert!(!is_controller(&Principal::anonymous()));
+    }
+}
@@ -1,9 +1,31 @@
-use crate::types::ServiceProvider;
-use std::collections::HashMap;
+use crate::types::{EventKind, HealthStatus, OptimizationSettings, ProviderDetail, ServiceProvider};
+use crate::clock::time;
+use std::collections::{HashMap, HashSet};
+
+// Weight given to the newest outcome when updating a provider's reliability
+const RELIABILITY_SMOOTHING: f64 = 0.1;
+// Latency samples averaged for health classification
+const HEALTH_LATENCY_SAMPLES: usize = 10;
+// Cost changes retained per provider
+const MAX_COST_HISTORY: usize = 100;
+// Latency samples needed before a provider has a baseline to compare against
+const ANOMALY_MIN_BASELINE: usize = 10;
 
 pub struct ServiceRegistry {
     providers: HashMap<String, ServiceProvider>,
     performance_history: HashMap<String, Vec<f64>>,
+    registered_at: HashMap<String, u64>,
+    // (window start, requests consumed) for providers with a quota
+    quota_usage: HashMap<String, (u64, u64)>,
+    // Providers barred from routing and registration regardless of is_active
+    blocklist: HashSet<String>,
+    // Providers out of routing whose reliability and performance history are frozen
+    maintenance: HashSet<String>,
+    // (timestamp, cost_per_request) each time a provider's base cost was set
+    cost_history: HashMap<String, Vec<(u64, u64)>>,
+    // Providers switched off by low reliability, with outcomes recorded since;
+    // manual (de)activation removes the entry so only these can recover by themselves
+    auto_deactivated: HashMap<String, u64>,
 }
 
 impl ServiceRegistry {
@@ -11,17 +33,41 @@ impl ServiceRegistry {
         Self {
             providers: HashMap::new(),
             performance_history: HashMap::new(),
+            registered_at: HashMap::new(),
+            quota_usage: HashMap::new(),
+            blocklist: HashSet::new(),
+            maintenance: HashSet::new(),
+            cost_history: HashMap::new(),
+            auto_deactivated: HashMap::new(),
         }
     }
 
-    pub fn register_provider(&mut self, provider: ServiceProvider) -> Result<(), String> {
+    /// Registers a provider. When `max_providers_per_chain` is set, registration
+    /// is rejected if any of the provider's chains already has that many
+    /// registered providers (active or not).
+    pub fn register_provider(&mut self, mut provider: ServiceProvider, max_providers_per_chain: Option<u64>) -> Result<String, String> {
+        if provider.id.is_empty() {
+            provider.id = generate_provider_id(&provider.name, &provider.api_endpoint);
+        }
+
+        if self.blocklist.contains(&provider.id) {
+            return Err("Provider is blocklisted".to_string());
+        }
+
         if self.providers.contains_key(&provider.id) {
             return Err("Provider already registered".to_string());
         }
 
+        if let Some(cap) = max_providers_per_chain {
+            self.check_chain_cap(&provider.supported_chains, cap, None)?;
+        }
+
         self.performance_history.insert(provider.id.clone(), Vec::new());
-        self.providers.insert(provider.id.clone(), provider);
-        Ok(())
+        self.registered_at.insert(provider.id.clone(), time());
+        self.cost_history.insert(provider.id.clone(), vec![(time(), provider.cost_per_request)]);
+        let provider_id = provider.id.clone();
+        self.providers.insert(provider_id.clone(), provider);
+        Ok(provider_id)
     }
 
     pub fn update_provider_performance(&mut self, provider_id: &str, response_time: f64) {
@@ -34,6 +80,104 @@ impl ServiceRegistry {
         }
     }
 
+    /// Records a latency sample and compares it with the provider's baseline
+    /// (the history before this sample). Returns an anomaly event when the
+    /// sample deviates by more than `sensitivity` standard deviations.
+    pub fn record_latency_sample(&mut self, provider_id: &str, response_time: f64, sensitivity: Option<f64>) -> Option<EventKind> {
+        if self.maintenance.contains(provider_id) {
+            return None;
+        }
+
+        let anomaly = match (sensitivity, self.performance_history.get(provider_id)) {
+            (Some(sensitivity), Some(history)) if history.len() >= ANOMALY_MIN_BASELINE => {
+                let n = history.len() as f64;
+                let mean = history.iter().sum::<f64>() / n;
+                let std_dev = (history.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / n).sqrt();
+                // A perfectly flat baseline would flag any change at all
+                (std_dev > 0.0 && (response_time - mean).abs() > sensitivity * std_dev).then(|| EventKind::ProviderAnomaly {
+                    provider_id: provider_id.to_string(),
+                    response_time,
+                    baseline_mean: mean,
+                    baseline_std_dev: std_dev,
+                })
+            }
+            _ => None,
+        };
+
+        self.update_provider_performance(provider_id, response_time);
+        anomaly
+    }
+
+    /// Folds a payment outcome into the provider's reliability score and reports
+    /// threshold crossings. Events fire only on the sample that crosses a level.
+    /// A provider auto-deactivated for low reliability is reactivated once, after
+    /// at least `reactivation_min_samples` further outcomes, its score is back at
+    /// or above `reactivation_threshold`.
+    pub fn record_provider_outcome(
+        &mut self,
+        provider_id: &str,
+        success: bool,
+        warn_threshold: f64,
+        deactivate_threshold: f64,
+        reactivation_threshold: Option<f64>,
+        reactivation_min_samples: u64,
+    ) -> Vec<EventKind> {
+        let mut events = Vec::new();
+        if self.maintenance.contains(provider_id) {
+            return events;
+        }
+
+        if let Some(provider) = self.providers.get_mut(provider_id) {
+            // Any reported outcome shows the provider is reachable
+            provider.last_ping = time();
+            let previous = provider.reliability_score;
+            let outcome = if success { 1.0 } else { 0.0 };
+            let current = previous * (1.0 - RELIABILITY_SMOOTHING) + outcome * RELIABILITY_SMOOTHING;
+            provider.reliability_score = current;
+
+            if previous >= warn_threshold && current < warn_threshold {
+                events.push(EventKind::ProviderReliabilityWarning {
+                    provider_id: provider_id.to_string(),
+                    reliability_score: current,
+                });
+            }
+
+            if previous >= deactivate_threshold && current < deactivate_threshold && provider.is_active {
+                provider.is_active = false;
+                self.auto_deactivated.insert(provider_id.to_string(), 0);
+                events.push(EventKind::ProviderAutoDeactivated {
+                    provider_id: provider_id.to_string(),
+                    reliability_score: current,
+                });
+            } else if let Some(samples) = self.auto_deactivated.get_mut(provider_id) {
+                *samples = samples.saturating_add(1);
+                let recovered = reactivation_threshold.is_some_and(|threshold| current >= threshold);
+                if recovered && *samples >= reactivation_min_samples {
+                    provider.is_active = true;
+                    self.auto_deactivated.remove(provider_id);
+                    events.push(EventKind::ProviderAutoReactivated {
+                        provider_id: provider_id.to_string(),
+                        reliability_score: current,
+                    });
+                }
+            }
+        }
+
+        events
+    }
+
+    /// Marks a provider reachable after a payment was dispatched through it, so
+    /// providers carrying routed traffic are not judged unresponsive just because
+    /// nobody reported usage for them. Ignored in maintenance, like outcomes.
+    pub fn record_routed_activity(&mut self, provider_id: &str) {
+        if self.maintenance.contains(provider_id) {
+            return;
+        }
+        if let Some(provider) = self.providers.get_mut(provider_id) {
+            provider.last_ping = time();
+        }
+    }
+
     pub fn get_best_provider(&self, chain: &str, max_cost: u64) -> Option<&ServiceProvider> {
         self.providers
             .values()
@@ -54,19 +198,366 @@ impl ServiceRegistry {
         self.providers.get(id)
     }
 
+    /// Requests left in the provider's current quota window, or `None` when the
+    /// provider has no quota.
+    pub fn remaining_quota(&self, provider_id: &str) -> Option<u64> {
+        let quota = self.providers.get(provider_id)?.quota.as_ref()?;
+        let used = match self.quota_usage.get(provider_id) {
+            Some(&(window_start, used)) if !window_expired(window_start, quota.window_seconds) => used,
+            _ => 0,
+        };
+        Some(quota.total.saturating_sub(used))
+    }
+
+    pub fn has_quota_remaining(&self, provider_id: &str) -> bool {
+        self.remaining_quota(provider_id) != Some(0)
+    }
+
+    pub fn consume_quota(&mut self, provider_id: &str) {
+        let Some(quota) = self.providers.get(provider_id).and_then(|p| p.quota.as_ref()) else {
+            return;
+        };
+
+        let now = time();
+        let usage = self.quota_usage.entry(provider_id.to_string()).or_insert((now, 0));
+        if window_expired(usage.0, quota.window_seconds) {
+            *usage = (now, 0);
+        }
+        usage.1 = usage.1.saturating_add(1);
+    }
+
+    /// Clears quota windows that have run out and returns how many of them had
+    /// been exhausted, i.e. how many providers just became routable again.
+    pub fn reset_expired_quotas(&mut self) -> u64 {
+        let providers = &self.providers;
+        let mut renewed = 0;
+        self.quota_usage.retain(|provider_id, &mut (window_start, used)| {
+            let Some(quota) = providers.get(provider_id).and_then(|p| p.quota.as_ref()) else {
+                return false;
+            };
+            if !window_expired(window_start, quota.window_seconds) {
+                return true;
+            }
+            if used >= quota.total {
+                renewed += 1;
+            }
+            false
+        });
+        renewed
+    }
+
+    pub fn get_provider_detail(&self, provider_id: &str, settings: &OptimizationSettings) -> Option<ProviderDetail> {
+        let provider = self.providers.get(provider_id)?.clone();
+        Some(ProviderDetail {
+            remaining_quota: self.remaining_quota(provider_id),
+            in_maintenance: self.maintenance.contains(provider_id),
+            health: self.health_status(&provider, time(), settings),
+            provider,
+        })
+    }
+
+    /// Classifies a provider from its recent behaviour:
+    /// - `Disabled`: inactive or blocklisted
+    /// - `Unresponsive`: no outcome reported and no payment routed through it for
+    ///   `unresponsive_after_seconds`
+    ///   (providers never heard from are not judged on this)
+    /// - `Degraded`: reliability below `warn_threshold`, or mean latency of the
+    ///   last few samples above `degraded_latency_seconds`
+    pub fn health_status(&self, provider: &ServiceProvider, now: u64, settings: &OptimizationSettings) -> HealthStatus {
+        if !provider.is_active || self.blocklist.contains(&provider.id) {
+            return HealthStatus::Disabled;
+        }
+
+        let silence = settings.unresponsive_after_seconds.saturating_mul(1_000_000_000);
+        if provider.last_ping > 0 && now.saturating_sub(provider.last_ping) > silence {
+            return HealthStatus::Unresponsive;
+        }
+
+        let recent_latency = self.performance_history.get(&provider.id).and_then(|history| {
+            let recent = &history[history.len().saturating_sub(HEALTH_LATENCY_SAMPLES)..];
+            (!recent.is_empty()).then(|| recent.iter().sum::<f64>() / recent.len() as f64)
+        });
+        let slow = recent_latency.is_some_and(|latency| latency > settings.degraded_latency_seconds);
+        if provider.reliability_score < settings.warn_threshold || slow {
+            return HealthStatus::Degraded;
+        }
+
+        HealthStatus::Healthy
+    }
+
+    /// Takes a provider out of routing without deactivating it. Outcomes
+    /// reported while in maintenance are ignored, so the provider returns at
+    /// the standing it had when it left. Ending maintenance counts as contact,
+    /// so the silence during it does not make the provider look unresponsive.
+    pub fn begin_maintenance(&mut self, provider_id: &str) -> Result<(), String> {
+        if !self.providers.contains_key(provider_id) {
+            return Err("Provider not found".to_string());
+        }
+        self.maintenance.insert(provider_id.to_string());
+        Ok(())
+    }
+
+    pub fn end_maintenance(&mut self, provider_id: &str) -> Result<(), String> {
+        if !self.maintenance.remove(provider_id) {
+            return Err("Provider is not in maintenance".to_string());
+        }
+        if let Some(provider) = self.providers.get_mut(provider_id) {
+            provider.last_ping = time();
+        }
+        Ok(())
+    }
+
+    pub fn in_maintenance(&self, provider_id: &str) -> bool {
+        self.maintenance.contains(provider_id)
+    }
+
+    pub fn blocklist_provider(&mut self, provider_id: &str) {
+        self.blocklist.insert(provider_id.to_string());
+    }
+
+    pub fn unblocklist_provider(&mut self, provider_id: &str) -> Result<(), String> {
+        if self.blocklist.remove(provider_id) {
+            Ok(())
+        } else {
+            Err("Provider is not blocklisted".to_string())
+        }
+    }
+
+    pub fn is_blocklisted(&self, provider_id: &str) -> bool {
+        self.blocklist.contains(provider_id)
+    }
+
+    pub fn list_blocklist(&self) -> Vec<String> {
+        let mut blocklist: Vec<String> = self.blocklist.iter().cloned().collect();
+        blocklist.sort();
+        blocklist
+    }
+
+    /// Registers `new_provider` as the successor of `old_id`: it inherits the old
+    /// provider's reliability score, performance history, current quota window
+    /// and cost history, and the old provider is deactivated. The old provider
+    /// does not count toward `max_providers_per_chain`, since it is being retired.
+    /// Nothing changes if the new provider cannot be registered. Returns the new
+    /// provider's id.
+    pub fn replace_provider(&mut self, old_id: &str, new_provider: ServiceProvider, max_providers_per_chain: Option<u64>) -> Result<String, String> {
+        if !self.providers.contains_key(old_id) {
+            return Err("Provider not found".to_string());
+        }
+        if let Some(cap) = max_providers_per_chain {
+            self.check_chain_cap(&new_provider.supported_chains, cap, Some(old_id))?;
+        }
+
+        // Fails for the old provider's own id, which is still registered
+        let new_id = self.register_provider(new_provider, None)?;
+
+        let history = self.performance_history.remove(old_id).unwrap_or_default();
+        self.performance_history.insert(new_id.clone(), history);
+        let reliability_score = self.providers.get(old_id).map(|p| p.reliability_score);
+        if let (Some(provider), Some(score)) = (self.providers.get_mut(&new_id), reliability_score) {
+            provider.reliability_score = score;
+        }
+        if let Some(usage) = self.quota_usage.remove(old_id) {
+            self.quota_usage.insert(new_id.clone(), usage);
+        }
+        let mut costs = self.cost_history.remove(old_id).unwrap_or_default();
+        costs.extend(self.cost_history.remove(&new_id).unwrap_or_default());
+        let excess = costs.len().saturating_sub(MAX_COST_HISTORY);
+        costs.drain(..excess);
+        self.cost_history.insert(new_id.clone(), costs);
+        self.deactivate_provider(old_id)?;
+        Ok(new_id)
+    }
+
+    /// Applies a provider's configuration from a manifest: unknown ids are
+    /// registered, known ones have their configuration replaced while keeping
+    /// live state (activation, reliability, last ping).
+    pub fn apply_manifest_provider(&mut self, provider: ServiceProvider, max_providers_per_chain: Option<u64>) -> Result<(), String> {
+        if self.blocklist.contains(&provider.id) {
+            return Err("Provider is blocklisted".to_string());
+        }
+        let Some(existing) = self.providers.get(&provider.id) else {
+            return self.register_provider(provider, max_providers_per_chain).map(|_| ());
+        };
+
+        let updated = ServiceProvider {
+            is_active: existing.is_active,
+            reliability_score: existing.reliability_score,
+            last_ping: existing.last_ping,
+            ..provider
+        };
+        if updated.cost_per_request != existing.cost_per_request {
+            self.update_provider_cost(&updated.id, updated.cost_per_request)?;
+        }
+        self.providers.insert(updated.id.clone(), updated);
+        Ok(())
+    }
+
+    /// Adds `chain` to a provider's supported chains, honoring the per-chain
+    /// provider cap. Adding a chain the provider already serves is a no-op.
+    pub fn add_provider_chain(&mut self, provider_id: &str, chain: &str, max_providers_per_chain: Option<u64>) -> Result<(), String> {
+        if self.providers.get(provider_id).ok_or("Provider not found")?.supported_chains.iter().any(|c| c == chain) {
+            return Ok(());
+        }
+
+        if let Some(cap) = max_providers_per_chain {
+            self.check_chain_cap(&[chain.to_string()], cap, Some(provider_id))?;
+        }
+
+        if let Some(provider) = self.providers.get_mut(provider_id) {
+            provider.supported_chains.push(chain.to_string());
+        }
+        Ok(())
+    }
+
+    /// Removes `chain` from a provider's supported chains. Removing its last
+    /// chain would leave the provider unroutable and requires `force`.
+    pub fn remove_provider_chain(&mut self, provider_id: &str, chain: &str, force: bool) -> Result<(), String> {
+        let provider = self.providers.get_mut(provider_id).ok_or("Provider not found")?;
+        let index = provider.supported_chains
+            .iter()
+            .position(|c| c == chain)
+            .ok_or_else(|| format!("Provider does not support chain {}", chain))?;
+        if provider.supported_chains.len() == 1 && !force {
+            return Err("Cannot remove the provider's last chain without force".to_string());
+        }
+
+        provider.supported_chains.remove(index);
+        Ok(())
+    }
+
+    /// Changes a provider's base cost and appends the change to its cost history.
+    pub fn update_provider_cost(&mut self, provider_id: &str, cost_per_request: u64) -> Result<(), String> {
+        let provider = self.providers.get_mut(provider_id).ok_or("Provider not found")?;
+        if provider.cost_per_request == cost_per_request {
+            return Ok(());
+        }
+        provider.cost_per_request = cost_per_request;
+
+        let history = self.cost_history.entry(provider_id.to_string()).or_default();
+        history.push((time(), cost_per_request));
+        if history.len() > MAX_COST_HISTORY {
+            history.remove(0);
+        }
+        Ok(())
+    }
+
+    /// Timestamped base costs for a provider, oldest first.
+    pub fn cost_history(&self, provider_id: &str) -> Vec<(u64, u64)> {
+        self.cost_history.get(provider_id).cloned().unwrap_or_default()
+    }
+
+    pub fn registered_at(&self, provider_id: &str) -> Option<u64> {
+        self.registered_at.get(provider_id).copied()
+    }
+
+    /// Last contact with a provider; one never pinged counts from registration.
+    pub fn last_seen(&self, provider: &ServiceProvider, now: u64) -> u64 {
+        match provider.last_ping {
+            0 => self.registered_at(&provider.id).unwrap_or(now),
+            last_ping => last_ping,
+        }
+    }
+
+    /// All providers, sorted by id.
     pub fn list_providers(&self) -> Vec<&ServiceProvider> {
-        self.providers.values().collect()
+        let mut providers: Vec<&ServiceProvider> = self.providers.values().collect();
+        providers.sort_by(|a, b| a.id.cmp(&b.id));
+        providers
+    }
+
+    /// Active providers with `reliability_score >= threshold`, optionally limited
+    /// to those serving `chain`. Sorted by id.
+    pub fn providers_min_reliability(&self, threshold: f64, chain: Option<&str>) -> Vec<&ServiceProvider> {
+        self.list_providers()
+            .into_iter()
+            .filter(|p| p.is_active && p.reliability_score >= threshold)
+            .filter(|p| chain.is_none_or(|chain| p.supported_chains.iter().any(|c| c == chain)))
+            .collect()
+    }
+
+    /// Active providers not heard from within `max_age`. A provider that has
+    /// never been pinged is aged from its registration instead.
+    pub fn count_stale_providers(&self, now: u64, max_age: u64) -> u64 {
+        self.providers
+            .values()
+            .filter(|p| p.is_active && now.saturating_sub(self.last_seen(p, now)) > max_age)
+            .count() as u64
+    }
+
+    /// Active provider count per chain. `known_chains` are always reported so
+    /// chains with no active coverage show up with a zero count.
+    pub fn chain_provider_counts(&self, known_chains: &[String]) -> Vec<(String, u64)> {
+        let mut counts: HashMap<String, u64> = known_chains
+            .iter()
+            .map(|chain| (chain.clone(), 0))
+            .collect();
+
+        for provider in self.providers.values() {
+            for chain in &provider.supported_chains {
+                let count = counts.entry(chain.clone()).or_insert(0);
+                if provider.is_active {
+                    *count += 1;
+                }
+            }
+        }
+
+        let mut counts: Vec<(String, u64)> = counts.into_iter().collect();
+        counts.sort();
+        counts
+    }
+
+    pub fn activate_provider(&mut self, provider_id: &str) -> Result<(), String> {
+        if let Some(provider) = self.providers.get_mut(provider_id) {
+            provider.is_active = true;
+            self.auto_deactivated.remove(provider_id);
+            Ok(())
+        } else {
+            Err("Provider not found".to_string())
+        }
+    }
+
+    /// Deactivates active providers on `chain`. Providers that also serve other
+    /// chains are left alone unless `include_multi_chain` is set.
+    pub fn deactivate_providers_by_chain(&mut self, chain: &str, include_multi_chain: bool) -> u64 {
+        let mut deactivated = 0;
+        for provider in self.providers.values_mut() {
+            let serves_chain = provider.supported_chains.iter().any(|c| c == chain);
+            let single_chain = provider.supported_chains.iter().all(|c| c == chain);
+            if provider.is_active && serves_chain && (single_chain || include_multi_chain) {
+                provider.is_active = false;
+                self.auto_deactivated.remove(&provider.id);
+                deactivated += 1;
+            }
+        }
+        deactivated
     }
 
     pub fn deactivate_provider(&mut self, provider_id: &str) -> Result<(), String> {
         if let Some(provider) = self.providers.get_mut(provider_id) {
             provider.is_active = false;
+            // Manual deactivation is sticky
+            self.auto_deactivated.remove(provider_id);
             Ok(())
         } else {
             Err("Provider not found".to_string())
         }
     }
 
+    // Fails if any of `chains` already has `cap` registered providers, not
+    // counting `ignoring`
+    fn check_chain_cap(&self, chains: &[String], cap: u64, ignoring: Option<&str>) -> Result<(), String> {
+        for chain in chains {
+            let registered = self.providers
+                .values()
+                .filter(|p| Some(p.id.as_str()) != ignoring && p.supported_chains.contains(chain))
+                .count() as u64;
+            if registered >= cap {
+                return Err(format!("Chain {} already has the maximum of {} providers", chain, cap));
+            }
+        }
+        Ok(())
+    }
+
     fn calculate_provider_score(&self, provider: &ServiceProvider) -> f64 {
         let cost_score = 1.0 / (provider.cost_per_request as f64 + 1.0);
         let reliability_score = provider.reliability_score;
@@ -86,3 +577,270 @@ impl ServiceRegistry {
         (cost_score * 0.3) + (reliability_score * 0.4) + (performance_score * 0.3)
     }
 }
+
+fn window_expired(window_start: u64, window_seconds: u64) -> bool {
+    time().saturating_sub(window_start) >= window_seconds.saturating_mul(1_000_000_000)
+}
+
+/// Stable id derived from a provider's name and endpoint (FNV-1a, so it does not
+/// change across toolchains the way `DefaultHasher` may).
+pub fn generate_provider_id(name: &str, api_endpoint: &str) -> String {
+    const FNV_OFFSET: u64 = 0xcbf29ce484222325;
+    const FNV_PRIME: u64 = 0x100000001b3;
+
+    let mut hash = FNV_OFFSET;
+    for byte in name.bytes().chain(std::iter::once(0)).chain(api_endpoint.bytes()) {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(FNV_PRIME);
+    }
+
+    format!("provider-{:016x}", hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock;
+    use crate::types::Quota;
+
+    #[test]
+    fn routed_traffic_keeps_provider_healthy() {
+        let settings = OptimizationSettings::default();
+        let mut registry = ServiceRegistry::new();
+        registry.register_provider(ServiceProvider::for_test("busy", &["ethereum"], 100), None).unwrap();
+        registry.register_provider(ServiceProvider::for_test("idle", &["ethereum"], 100), None).unwrap();
+        registry.record_routed_activity("busy");
+        registry.record_routed_activity("idle");
+
+        for _ in 0..3 {
+            clock::advance_seconds(settings.unresponsive_after_seconds / 2 + 1);
+            registry.record_routed_activity("busy");
+        }
+
+        let busy = registry.get_provider("busy").unwrap();
+        let idle = registry.get_provider("idle").unwrap();
+        assert!(matches!(registry.health_status(busy, clock::time(), &settings), HealthStatus::Healthy));
+        assert!(matches!(registry.health_status(idle, clock::time(), &settings), HealthStatus::Unresponsive));
+    }
+
+    #[test]
+    fn routed_activity_ignored_in_maintenance() {
+        let mut registry = ServiceRegistry::new();
+        registry.register_provider(ServiceProvider::for_test("p", &["ethereum"], 100), None).unwrap();
+        registry.begin_maintenance("p").unwrap();
+        registry.record_routed_activity("p");
+        assert_eq!(registry.get_provider("p").unwrap().last_ping, 0);
+    }
+
+    #[test]
+    fn exhausted_quota_renews_once_window_passes() {
+        let mut provider = ServiceProvider::for_test("p", &["ethereum"], 100);
+        provider.quota = Some(Quota { total: 2, window_seconds: 60 });
+        let mut registry = ServiceRegistry::new();
+        registry.register_provider(provider, None).unwrap();
+        registry.consume_quota("p");
+        registry.consume_quota("p");
+        assert!(!registry.has_quota_remaining("p"));
+        assert_eq!(registry.reset_expired_quotas(), 0);
+
+        clock::advance_seconds(60);
+        assert_eq!(registry.reset_expired_quotas(), 1);
+        assert_eq!(registry.remaining_quota("p"), Some(2));
+        assert_eq!(registry.reset_expired_quotas(), 0);
+    }
+
+    #[test]
+    fn provider_returns_from_maintenance_responsive() {
+        let settings = OptimizationSettings::default();
+        let mut registry = ServiceRegistry::new();
+        registry.register_provider(ServiceProvider::for_test("p", &["ethereum"], 100), None).unwrap();
+        registry.record_routed_activity("p");
+        registry.begin_maintenance("p").unwrap();
+        clock::advance_seconds(settings.unresponsive_after_seconds * 3);
+        registry.end_maintenance("p").unwrap();
+
+        let provider = registry.get_provider("p").unwrap();
+        assert!(matches!(registry.health_status(provider, clock::time(), &settings), HealthStatus::Healthy));
+    }
+
+    #[test]
+    fn never_pinged_provider_is_aged_from_registration() {
+        let max_age = 3600 * 1_000_000_000;
+        let mut registry = ServiceRegistry::new();
+        registry.register_provider(ServiceProvider::for_test("new", &["ethereum"], 100), None).unwrap();
+        assert_eq!(registry.count_stale_providers(clock::time(), max_age), 0);
+
+        clock::advance_seconds(3601);
+        assert_eq!(registry.count_stale_providers(clock::time(), max_age), 1);
+        registry.record_routed_activity("new");
+        assert_eq!(registry.count_stale_providers(clock::time(), max_age), 0);
+    }
+
+    #[test]
+    fn reliability_warning_fires_once_at_crossing() {
+        let mut registry = ServiceRegistry::new();
+        registry.register_provider(ServiceProvider::for_test("p", &["ethereum"], 100), None).unwrap();
+
+        let mut warnings = 0;
+        let mut deactivations = 0;
+        for _ in 0..30 {
+            for event in registry.record_provider_outcome("p", false, 0.9, 0.5, None, 10) {
+                match event {
+                    EventKind::ProviderReliabilityWarning { .. } => warnings += 1,
+                    EventKind::ProviderAutoDeactivated { .. } => deactivations += 1,
+                    _ => {}
+                }
+            }
+        }
+
+        assert_eq!((warnings, deactivations), (1, 1));
+        assert!(!registry.get_provider("p").unwrap().is_active);
+    }
+
+    #[test]
+    fn successor_replaces_provider_at_chain_cap() {
+        let mut old = ServiceProvider::for_test("old", &["ethereum"], 100);
+        old.quota = Some(Quota { total: 5, window_seconds: 60 });
+        let mut registry = ServiceRegistry::new();
+        registry.register_provider(old, None).unwrap();
+        registry.consume_quota("old");
+        registry.update_provider_cost("old", 120).unwrap();
+
+        let mut new = ServiceProvider::for_test("new", &["ethereum"], 90);
+        new.quota = Some(Quota { total: 5, window_seconds: 60 });
+        assert!(registry.register_provider(new.clone(), Some(1)).is_err());
+        assert_eq!(registry.replace_provider("old", new, Some(1)), Ok("new".to_string()));
+
+        assert_eq!(registry.remaining_quota("new"), Some(4));
+        let costs: Vec<u64> = registry.cost_history("new").into_iter().map(|(_, cost)| cost).collect();
+        assert_eq!(costs, vec![100, 120, 90]);
+        assert!(!registry.get_provider("old").unwrap().is_active);
+    }
+
+    #[test]
+    fn adding_a_chain_respects_the_chain_cap() {
+        let mut registry = ServiceRegistry::new();
+        for (id, chain) in [("a", "ethereum"), ("b", "ethereum"), ("c", "solana")] {
+            registry.register_provider(ServiceProvider::for_test(id, &[chain], 100), None).unwrap();
+        }
+
+        assert_eq!(
+            registry.add_provider_chain("c", "ethereum", Some(2)),
+            Err("Chain ethereum already has the maximum of 2 providers".to_string())
+        );
+        assert!(registry.add_provider_chain("a", "ethereum", Some(2)).is_ok());
+        assert!(registry.add_provider_chain("c", "ethereum", Some(3)).is_ok());
+        assert_eq!(registry.get_provider("c").unwrap().supported_chains, vec!["solana".to_string(), "ethereum".to_string()]);
+    }
+
+    #[test]
+    fn generated_ids_are_stable_and_separate_fields() {
+        let id = generate_provider_id("Infura", "https://mainnet.infura.io");
+        assert_eq!(id, generate_provider_id("Infura", "https://mainnet.infura.io"));
+        assert!(id.starts_with("provider-") && id.len() == "provider-".len() + 16);
+        assert_ne!(generate_provider_id("ab", "c"), generate_provider_id("a", "bc"));
+    }
+
+    #[test]
+    fn registration_without_id_uses_generated_id() {
+        let mut provider = ServiceProvider::for_test("", &["ethereum"], 100);
+        provider.name = "Infura".to_string();
+        provider.api_endpoint = "https://mainnet.infura.io".to_string();
+        let mut registry = ServiceRegistry::new();
+
+        let id = registry.register_provider(provider.clone(), None).unwrap();
+        assert_eq!(id, "provider-bb08bfe88352cfe2");
+        assert_eq!(registry.get_provider(&id).unwrap().id, id);
+        assert_eq!(registry.register_provider(provider, None), Err("Provider already registered".to_string()));
+    }
+
+    #[test]
+    fn chain_counts_include_uncovered_known_chains() {
+        let mut registry = ServiceRegistry::new();
+        for (id, chains) in [("a", &["ethereum", "polygon"][..]), ("b", &["ethereum"][..]), ("c", &["base"][..])] {
+            registry.register_provider(ServiceProvider::for_test(id, chains, 100), None).unwrap();
+        }
+        registry.deactivate_provider("c").unwrap();
+
+        let known = vec!["ethereum".to_string(), "solana".to_string()];
+        assert_eq!(
+            registry.chain_provider_counts(&known),
+            vec![
+                ("base".to_string(), 0),
+                ("ethereum".to_string(), 2),
+                ("polygon".to_string(), 1),
+                ("solana".to_string(), 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn bulk_deactivation_spares_multi_chain_providers_unless_asked() {
+        let mut registry = ServiceRegistry::new();
+        for (id, chains) in [("solo", &["ethereum"][..]), ("multi", &["ethereum", "polygon"][..]), ("other", &["polygon"][..])] {
+            registry.register_provider(ServiceProvider::for_test(id, chains, 100), None).unwrap();
+        }
+        let active = |registry: &ServiceRegistry, id: &str| registry.get_provider(id).unwrap().is_active;
+
+        assert_eq!(registry.deactivate_providers_by_chain("ethereum", false), 1);
+        assert!(!active(&registry, "solo") && active(&registry, "multi"));
+        assert_eq!(registry.deactivate_providers_by_chain("ethereum", true), 1);
+        assert!(!active(&registry, "multi") && active(&registry, "other"));
+    }
+
+    #[test]
+    fn latency_far_outside_the_baseline_is_an_anomaly() {
+        let mut registry = ServiceRegistry::new();
+        registry.register_provider(ServiceProvider::for_test("p", &["ethereum"], 100), None).unwrap();
+        for n in 0..10 {
+            let sample = if n % 2 == 0 { 1.0 } else { 1.2 };
+            assert!(registry.record_latency_sample("p", sample, Some(3.0)).is_none());
+        }
+
+        assert!(registry.record_latency_sample("p", 1.25, Some(3.0)).is_none());
+        let anomaly = registry.record_latency_sample("p", 2.0, Some(3.0));
+        assert!(matches!(anomaly, Some(EventKind::ProviderAnomaly { ref provider_id, .. }) if provider_id == "p"));
+        assert!(registry.record_latency_sample("p", 5.0, None).is_none());
+    }
+
+    #[test]
+    fn cost_history_records_only_actual_changes() {
+        let mut registry = ServiceRegistry::new();
+        registry.register_provider(ServiceProvider::for_test("p", &["ethereum"], 100), None).unwrap();
+        let registered_at = clock::time();
+        clock::advance_seconds(5);
+        registry.update_provider_cost("p", 100).unwrap();
+        registry.update_provider_cost("p", 80).unwrap();
+
+        assert_eq!(registry.cost_history("p"), vec![(registered_at, 100), (clock::time(), 80)]);
+        assert_eq!(registry.get_provider("p").unwrap().cost_per_request, 80);
+        assert_eq!(registry.update_provider_cost("missing", 1), Err("Provider not found".to_string()));
+        for cost in 0..200 {
+            registry.update_provider_cost("p", cost).unwrap();
+        }
+        assert_eq!(registry.cost_history("p").len(), MAX_COST_HISTORY);
+    }
+
+    #[test]
+    fn auto_deactivated_provider_recovers_but_manual_deactivation_sticks() {
+        let mut registry = ServiceRegistry::new();
+        registry.register_provider(ServiceProvider::for_test("p", &["ethereum"], 100), None).unwrap();
+        let record = |registry: &mut ServiceRegistry, success: bool| {
+            registry
+                .record_provider_outcome("p", success, 0.9, 0.8, Some(0.9), 3)
+                .into_iter()
+                .any(|event| matches!(event, EventKind::ProviderAutoReactivated { .. }))
+        };
+        while registry.get_provider("p").unwrap().is_active {
+            record(&mut registry, false);
+        }
+
+        let reactivated_after = (1..=50).find(|_| record(&mut registry, true));
+        assert!(reactivated_after.is_some_and(|samples| samples >= 3));
+        assert!(registry.get_provider("p").unwrap().is_active);
+
+        registry.deactivate_provider("p").unwrap();
+        assert!((0..50).all(|_| !record(&mut registry, true)));
+        assert!(!registry.get_provider("p").unwrap().is_active);
+    }
+}
@@ -1,5 +1,6 @@
-use candid::CandidType;
+use candid::{CandidType, Principal};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 
 #[derive(CandidType, Clone, Debug, Deserialize, Serialize)]
 pub struct ServiceProvider {
@@ -11,6 +12,84 @@ pub struct ServiceProvider {
     pub reliability_score: f64,
     pub last_ping: u64,
     pub is_active: bool,
+    pub retry_policy: Option<RetryPolicy>,
+    pub quota: Option<Quota>,
+    // Amount-based prices overriding cost_per_request; None means flat pricing
+    pub cost_tiers: Option<Vec<CostTier>>,
+    // Tokens the provider can settle; None or empty means any token
+    pub supported_tokens: Option<Vec<String>>,
+    // Payment volume the provider can absorb at once, in the chain's base unit;
+    // None means unbounded
+    pub capacity: Option<u64>,
+    // HMAC-SHA256 over the declared configuration, required while registration verification is on
+    pub registration_signature: Option<Vec<u8>>,
+}
+
+#[cfg(test)]
+impl ServiceProvider {
+    pub(crate) fn for_test(id: &str, chains: &[&str], cost_per_request: u64) -> Self {
+        Self {
+            id: id.to_string(),
+            name: id.to_string(),
+            api_endpoint: format!("https://{}.example", id),
+            supported_chains: chains.iter().map(|c| c.to_string()).collect(),
+            cost_per_request,
+            reliability_score: 0.95,
+            last_ping: 0,
+            is_active: true,
+            retry_policy: None,
+            quota: None,
+            cost_tiers: None,
+            supported_tokens: None,
+            capacity: None,
+            registration_signature: None,
+        }
+    }
+}
+
+#[derive(CandidType, Clone, Debug, Deserialize, Serialize)]
+pub struct CostTier {
+    pub min_amount: u64,
+    pub cost_per_request: u64,
+}
+
+#[derive(CandidType, Clone, Debug, Deserialize, Serialize)]
+pub struct Quota {
+    pub total: u64,
+    pub window_seconds: u64,
+}
+
+#[derive(CandidType, Clone, Debug, Deserialize, Serialize)]
+pub struct SpendLimit {
+    pub amount: u64,
+    pub window_seconds: u64,
+}
+
+#[derive(CandidType, Clone, Debug, Deserialize, Serialize)]
+pub struct ProviderDetail {
+    pub provider: ServiceProvider,
+    pub remaining_quota: Option<u64>,
+    pub in_maintenance: bool,
+    pub health: HealthStatus,
+}
+
+#[derive(CandidType, Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub enum HealthStatus {
+    Healthy,
+    Degraded,
+    Unresponsive,
+    Disabled,
+}
+
+#[derive(CandidType, Clone, Debug, Deserialize, Serialize)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self { max_retries: 3 }
+    }
 }
 
 #[derive(CandidType, Clone, Debug, Deserialize, Serialize)]
@@ -23,6 +102,60 @@ pub struct PaymentRequest {
     pub metadata: String,
     pub timestamp: u64,
     pub status: PaymentStatus,
+    // Higher runs first under the Priority strategy; None counts as 0
+    pub priority: Option<u8>,
+    pub receipt: Option<TxReceipt>,
+    pub memo: Option<Vec<u8>>,
+    pub execute_after: Option<u64>,
+    // Some(true) skips the replay check
+    pub allow_duplicate: Option<bool>,
+    pub tags: Option<Vec<String>>,
+    pub token: Option<String>,
+    pub failure_reason: Option<String>,
+    // Set by the canister to the caller of submit_payment
+    pub submitter: Option<Principal>,
+    // Providers the router must not pick for this payment
+    pub exclude_providers: Option<Vec<String>>,
+}
+
+#[cfg(test)]
+impl PaymentRequest {
+    pub(crate) fn for_test(id: &str, chain: &str, amount: u64, recipient: &str) -> Self {
+        Self {
+            id: id.to_string(),
+            provider_id: String::new(),
+            chain: chain.to_string(),
+            amount,
+            recipient: recipient.to_string(),
+            metadata: String::new(),
+            timestamp: 0,
+            status: PaymentStatus::Pending,
+            priority: None,
+            receipt: None,
+            memo: None,
+            execute_after: None,
+            allow_duplicate: None,
+            tags: None,
+            token: None,
+            failure_reason: None,
+            submitter: None,
+            exclude_providers: None,
+        }
+    }
+}
+
+#[derive(CandidType, Clone, Debug, Deserialize, Serialize)]
+pub struct TxReceipt {
+    pub tx_hash: String,
+    pub block_number: u64,
+    pub gas_used: u64,
+    pub status: TxStatus,
+}
+
+#[derive(CandidType, Clone, Debug, Deserialize, Serialize)]
+pub enum TxStatus {
+    Success,
+    Reverted,
 }
 
 #[derive(CandidType, Clone, Debug, Deserialize, Serialize)]
@@ -32,6 +165,7 @@ pub enum PaymentStatus {
     Completed,
     Failed,
     Cancelled,
+    Blocked { reason: String },
 }
 
 #[derive(CandidType, Clone, Debug, Deserialize, Serialize)]
@@ -42,6 +176,16 @@ pub struct UsageMetrics {
     pub total_volume: u64,
     pub average_response_time: f64,
     pub cost_efficiency: f64,
+    pub on_time_rate: f64,
+    pub units: MetricsUnits,
+}
+
+#[derive(CandidType, Clone, Debug, Deserialize, Serialize)]
+pub struct MetricsUnits {
+    pub volume_unit: String,
+    pub response_time_unit: String,
+    pub cost_efficiency_unit: String,
+    pub cost_efficiency_scale: f64,
 }
 
 #[derive(CandidType, Clone, Debug, Deserialize, Serialize)]
@@ -51,6 +195,56 @@ pub struct OptimizationSettings {
     pub reliability_threshold: f64,
     pub auto_optimization_enabled: bool,
     pub rebalance_frequency: u64,
+    pub recipient_provider_pin: HashMap<String, String>,
+    pub warn_threshold: f64,
+    pub deactivate_threshold: f64,
+    pub cost_efficiency_scale: f64,
+    pub preferred_chain_mode: PreferredChainMode,
+    pub success_rate_prior: f64,
+    pub success_rate_prior_weight: f64,
+    pub warmup_period_seconds: u64,
+    pub warmup_traffic_fraction: f64,
+    pub default_metrics_window_seconds: u64,
+    pub metrics_cache_ttl_seconds: u64,
+    pub chain_cost_learning_rate: Option<f64>,
+    pub max_providers_per_chain: Option<u64>,
+    pub anomaly_sensitivity: Option<f64>,
+    pub min_samples_for_full_trust: u64,
+    pub batch_amortization_fraction: f64,
+    pub normalize_scores: bool,
+    pub degraded_latency_seconds: f64,
+    pub unresponsive_after_seconds: u64,
+    pub recent_failure_penalty: f64,
+    pub recent_failure_window_seconds: u64,
+    pub chain_preferences: Vec<ChainPreference>,
+    pub capacity_weight: f64,
+    // Bridging fee per (from chain, to chain) when a payment is moved off its
+    // requested chain; pairs without an entry are not cost-checked
+    pub cross_chain_cost: HashMap<(String, String), u64>,
+    // Settlement latency target per chain, in seconds, for on_time_rate
+    pub target_settlement_seconds: HashMap<String, f64>,
+    // Score an auto-deactivated provider must recover to, over at least
+    // reactivation_min_samples outcomes, to be reactivated; None keeps it off
+    pub reactivation_threshold: Option<f64>,
+    pub reactivation_min_samples: u64,
+    pub stale_data_seconds: u64,
+    pub stale_data_penalty: f64,
+}
+
+/// A chain in routing priority order. Higher `weight` ranks first; fallback
+/// chains are only routed to when no primary chain has an eligible provider.
+#[derive(CandidType, Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct ChainPreference {
+    pub name: String,
+    pub weight: f64,
+    pub is_fallback: bool,
+}
+
+#[derive(CandidType, Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub enum PreferredChainMode {
+    Off,
+    Suggest,
+    Enforce,
 }
 
 impl Default for OptimizationSettings {
@@ -59,8 +253,178 @@ impl Default for OptimizationSettings {
             max_cost_per_transaction: 1000000, // 0.01 USD in wei equivalent
             preferred_chains: vec!["REI".to_string(), "Polygon".to_string()],
             reliability_threshold: 0.95,
-            auto_optimization_enabled: true,
+            auto_optimization_enabled: false, // rebalancing rewrites preferred_chains; opt in
             rebalance_frequency: 3600, // 1 hour in seconds
+            recipient_provider_pin: HashMap::new(),
+            warn_threshold: 0.9,
+            deactivate_threshold: 0.8,
+            cost_efficiency_scale: 1000000.0, // per million wei
+            preferred_chain_mode: PreferredChainMode::Off,
+            success_rate_prior: 0.5,
+            success_rate_prior_weight: 2.0, // Laplace smoothing: (successes + 1) / (samples + 2)
+            warmup_period_seconds: 0, // disabled
+            warmup_traffic_fraction: 0.2,
+            default_metrics_window_seconds: 3600,
+            metrics_cache_ttl_seconds: 60,
+            chain_cost_learning_rate: None, // equal-weight running average
+            max_providers_per_chain: None, // unbounded
+            anomaly_sensitivity: Some(3.0), // standard deviations from baseline
+            min_samples_for_full_trust: 0, // reported reliability is trusted immediately
+            batch_amortization_fraction: 0.0, // per-request cost is fully marginal
+            normalize_scores: false,
+            degraded_latency_seconds: 5.0,
+            unresponsive_after_seconds: 3600, // 1 hour without any reported outcome
+            recent_failure_penalty: 0.5, // score units, decaying to zero over the window
+            recent_failure_window_seconds: 0, // disabled
+            chain_preferences: Vec::new(), // derived from preferred_chains, in list order
+            capacity_weight: 0.0, // capacity does not affect routing
+            cross_chain_cost: HashMap::new(),
+            target_settlement_seconds: HashMap::new(),
+            reactivation_threshold: None, // auto-deactivation needs manual reactivation
+            reactivation_min_samples: 10,
+            stale_data_seconds: 0, // freshness does not affect routing
+            stale_data_penalty: 0.05, // score units per stale source
+        }
+    }
+}
+
+#[derive(CandidType, Clone, Debug, Deserialize, Serialize)]
+pub struct ProcessorSettings {
+    pub max_payments_per_tick: u64,
+    pub retry_policy: RetryPolicy,
+    pub strategy: ProcessingStrategy,
+    pub processing_timeout_seconds: u64,
+    pub replay_window_seconds: u64,
+    pub batch_max_size: u64,
+    pub batch_window_seconds: u64,
+    pub decision_log_enabled: bool,
+    pub simulated_latency_ms: Option<u64>,
+    pub retry_backoff_seconds: u64,
+    pub retry_jitter_fraction: f64,
+    // Per-chain override of processing_timeout_seconds
+    pub confirmation_timeout_seconds: HashMap<String, u64>,
+    // Smallest accepted amount per chain; unlisted chains have no minimum
+    pub min_payment_amount: HashMap<String, u64>,
+    // Consecutive failed attempts, across all payments, that raise a
+    // ProcessingDegraded alert; 0 disables alerting
+    pub alert_after_consecutive_failures: u64,
+    // Canister notified via `processing_degraded(nat64)` when the alert fires
+    pub alert_canister: Option<Principal>,
+    // Gas price per chain above which payments on it are deferred
+    pub max_gas_price: HashMap<String, u64>,
+    // Upper bound on metadata, on all tags together and on the memo
+    pub max_metadata_bytes: u64,
+    // Round-robin each tick's selection across submitting principals
+    pub fair_by_submitter: bool,
+    // Cost model for estimate_batch_cost
+    pub cycles_per_signature: u64,
+    pub cycles_per_outcall: u64,
+    // Confirmation polls per transaction by chain; unlisted chains poll once
+    pub confirmation_polls: HashMap<String, u64>,
+    // Cap on what one submitter may have pending plus settled within the window
+    pub principal_spend_limit: Option<SpendLimit>,
+}
+
+#[derive(CandidType, Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub enum ProcessingStrategy {
+    Fifo,
+    Priority,
+    CostOptimized,
+    Deadline,
+}
+
+impl Default for ProcessorSettings {
+    fn default() -> Self {
+        Self {
+            max_payments_per_tick: 100,
+            retry_policy: RetryPolicy::default(),
+            strategy: ProcessingStrategy::Priority,
+            processing_timeout_seconds: 300,
+            replay_window_seconds: 86400, // 24 hours
+            batch_max_size: 1, // batching disabled
+            batch_window_seconds: 0,
+            decision_log_enabled: false,
+            simulated_latency_ms: None, // simulated settlements are not recorded as usage
+            retry_backoff_seconds: 0, // retry on the next tick
+            retry_jitter_fraction: 0.2, // +/-20% of the backoff
+            confirmation_timeout_seconds: HashMap::new(),
+            min_payment_amount: HashMap::new(),
+            alert_after_consecutive_failures: 0, // disabled
+            alert_canister: None,
+            max_gas_price: HashMap::new(),
+            max_metadata_bytes: 4096,
+            fair_by_submitter: false,
+            cycles_per_signature: 26_153_846_153, // threshold ECDSA signature on the fiduciary subnet
+            cycles_per_outcall: 50_000_000, // HTTPS outcall on a 13-node subnet, before payload bytes
+            confirmation_polls: HashMap::new(),
+            principal_spend_limit: None, // no per-principal cap
         }
     }
 }
+
+#[derive(CandidType, Clone, Debug, Deserialize, Serialize)]
+pub struct CsvPage {
+    pub csv: String,
+    pub next_offset: Option<u64>,
+}
+
+/// Payments submitted within a time range, split into mutually exclusive
+/// outcome buckets. Settled, failed, cancelled and outstanding always sum to
+/// the submitted totals.
+#[derive(CandidType, Clone, Debug, Default, Deserialize, Serialize)]
+pub struct ReconciliationReport {
+    pub start_ts: u64,
+    pub end_ts: u64,
+    pub submitted_count: u64,
+    pub submitted_volume: u64,
+    pub settled_count: u64,
+    pub settled_volume: u64,
+    pub failed_count: u64,
+    pub failed_volume: u64,
+    pub cancelled_count: u64,
+    pub cancelled_volume: u64,
+    pub outstanding_count: u64,
+    pub outstanding_volume: u64,
+}
+
+#[derive(CandidType, Clone, Debug, Deserialize, Serialize)]
+pub struct Readiness {
+    pub healthy: bool,
+    pub pending_backlog: u64,
+    pub cycles_low: bool,
+    pub stale_providers: u64,
+    pub timestamp: u64,
+}
+
+#[derive(CandidType, Clone, Debug, Deserialize, Serialize)]
+pub enum EventKind {
+    ProviderReliabilityWarning { provider_id: String, reliability_score: f64 },
+    ProviderAutoDeactivated { provider_id: String, reliability_score: f64 },
+    ProviderAutoReactivated { provider_id: String, reliability_score: f64 },
+    ProviderAnomaly { provider_id: String, response_time: f64, baseline_mean: f64, baseline_std_dev: f64 },
+    ChainPreferenceShifted { from_chain: String, to_chain: String, reason: String },
+    ServiceHalted { reason: String },
+    ServiceResumed,
+    ProcessingDegraded { consecutive_failures: u64 },
+}
+
+#[derive(CandidType, Clone, Debug, Deserialize, Serialize)]
+pub struct Event {
+    pub id: u64,
+    pub timestamp: u64,
+    pub kind: EventKind,
+}
+
+#[derive(CandidType, Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub enum AddressFormat {
+    EvmHex,
+    Base58,
+    IcpPrincipal,
+}
+
+#[derive(CandidType, Clone, Debug, Deserialize, Serialize)]
+pub struct KnownChain {
+    pub name: String,
+    pub address_format: AddressFormat,
+    pub max_memo_bytes: u32,
+}